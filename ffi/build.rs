@@ -0,0 +1,24 @@
+#[cfg(feature = "header")]
+extern crate cbindgen;
+
+
+// With the `header` feature, keeps include/tetris.h in step with the C
+// interface in src/lib.rs. Otherwise the checked in header is used as is.
+#[cfg(feature = "header")]
+fn main() {
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    match cbindgen::generate(&crate_dir) {
+        Ok(bindings) => {
+            bindings.write_to_file("include/tetris.h");
+        },
+        Err(e) => panic!("cannot generate include/tetris.h: {}", e),
+    }
+}
+
+
+#[cfg(not(feature = "header"))]
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+}
@@ -0,0 +1,150 @@
+#ifndef TETRIS_H
+#define TETRIS_H
+
+/* Generated from src/lib.rs by build.rs with the header feature, do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/*
+ Updates per second. Timers in game modes count in ticks of this length.
+ */
+#define TETRIS_TICKS_PER_SECOND 60
+
+/*
+ Inputs for `tetris_press`. Holding a key down is a press followed, some
+ ticks later, by its release.
+ */
+typedef enum TetrisAction {
+  TetrisAction_Rotate = 0,
+  TetrisAction_RotateCcw,
+  TetrisAction_Rotate180,
+  TetrisAction_Left,
+  TetrisAction_LeftReleased,
+  TetrisAction_Right,
+  TetrisAction_RightReleased,
+  TetrisAction_Down,
+  TetrisAction_DownReleased,
+  TetrisAction_HardDrop,
+  TetrisAction_Hold,
+  TetrisAction_Pause,
+  TetrisAction_Restart,
+} TetrisAction;
+
+/*
+ What `tetris_cells` writes for each cell.
+ */
+typedef enum TetrisCell {
+  TetrisCell_Empty = 0,
+  TetrisCell_Locked,
+  TetrisCell_Active,
+} TetrisCell;
+
+typedef enum TetrisState {
+  TetrisState_Falling = 0,
+  TetrisState_Clearing,
+  TetrisState_Locking,
+  TetrisState_Paused,
+  TetrisState_GameOver,
+  TetrisState_Finished,
+} TetrisState;
+
+/*
+ A game and the inputs waiting for its next tick.
+ */
+typedef struct TetrisGame TetrisGame;
+
+/*
+ Starts a marathon game at `level` (0-20) with the default rules. The
+ piece sequence depends only on `seed`. Returns null for a bad level.
+ */
+struct TetrisGame *tetris_new(uint64_t seed, uint8_t level);
+
+/*
+ # Safety
+
+ `game` must be null or come from `tetris_new`, and is gone afterwards.
+ */
+void tetris_free(struct TetrisGame *game);
+
+/*
+ Queues `action`, one of `TetrisAction`, for the next tick. Returns
+ false if it is not a known action.
+
+ # Safety
+
+ `game` must be null or a live game from `tetris_new`.
+ */
+bool tetris_press(struct TetrisGame *game, int action);
+
+/*
+ Applies the queued inputs and advances the game by one update.
+
+ # Safety
+
+ `game` must be null or a live game from `tetris_new`.
+ */
+void tetris_tick(struct TetrisGame *game);
+
+/*
+ Columns in the well.
+
+ # Safety
+
+ `game` must be null or a live game from `tetris_new`.
+ */
+int tetris_width(const struct TetrisGame *game);
+
+/*
+ Rows in the well.
+
+ # Safety
+
+ `game` must be null or a live game from `tetris_new`.
+ */
+int tetris_height(const struct TetrisGame *game);
+
+/*
+ Writes up to `len` `TetrisCell`s into `cells`, a row at a time from the
+ bottom row up, and returns how many cells the well has.
+
+ # Safety
+
+ `game` must be null or a live game from `tetris_new`, and `cells` must
+ be null or point to at least `len` bytes.
+ */
+uintptr_t tetris_cells(const struct TetrisGame *game, uint8_t *cells, uintptr_t len);
+
+/*
+ # Safety
+
+ `game` must be null or a live game from `tetris_new`.
+ */
+uint32_t tetris_score(const struct TetrisGame *game);
+
+/*
+ # Safety
+
+ `game` must be null or a live game from `tetris_new`.
+ */
+uint32_t tetris_lines(const struct TetrisGame *game);
+
+/*
+ # Safety
+
+ `game` must be null or a live game from `tetris_new`.
+ */
+uint8_t tetris_level(const struct TetrisGame *game);
+
+/*
+ A null game is over.
+
+ # Safety
+
+ `game` must be null or a live game from `tetris_new`.
+ */
+enum TetrisState tetris_state(const struct TetrisGame *game);
+
+#endif /* TETRIS_H */
@@ -0,0 +1,334 @@
+// A C interface to the engine, for embedding it in programs not written in
+// Rust. `include/tetris.h` is generated from this file by building with the
+// `header` feature.
+//
+// A game is an opaque pointer made by `tetris_new` and released with
+// `tetris_free`. Inputs pressed with `tetris_press` are applied, in order,
+// at the start of the next `tetris_tick`, which advances the game by one
+// update of game time, `TETRIS_TICKS_PER_SECOND` of which make a second
+// however fast the game is ticked.
+//
+// No panic unwinds into C: a function that panics returns what it would
+// for a null game instead.
+
+extern crate tetris;
+
+use std::os::raw::c_int;
+use std::panic::{ self, AssertUnwindSafe };
+use std::ptr;
+use std::slice;
+
+use tetris::{ Action, Engine, Rules, States };
+
+
+/// Updates per second. Timers in game modes count in ticks of this length.
+pub const TETRIS_TICKS_PER_SECOND: u64 = 60;
+
+
+/// A game and the inputs waiting for its next tick.
+pub struct TetrisGame {
+    engine: Engine,
+    actions: Vec<Action>,
+}
+
+
+/// Inputs for `tetris_press`. Holding a key down is a press followed, some
+/// ticks later, by its release.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TetrisAction {
+    Rotate = 0,
+    RotateCcw,
+    Rotate180,
+    Left,
+    LeftReleased,
+    Right,
+    RightReleased,
+    Down,
+    DownReleased,
+    HardDrop,
+    Hold,
+    Pause,
+    Restart,
+}
+
+
+const ACTIONS: [(TetrisAction, Action); 13] = [
+    (TetrisAction::Rotate, Action::Rotate),
+    (TetrisAction::RotateCcw, Action::RotateCcw),
+    (TetrisAction::Rotate180, Action::Rotate180),
+    (TetrisAction::Left, Action::Left),
+    (TetrisAction::LeftReleased, Action::LeftReleased),
+    (TetrisAction::Right, Action::Right),
+    (TetrisAction::RightReleased, Action::RightReleased),
+    (TetrisAction::Down, Action::Down),
+    (TetrisAction::DownReleased, Action::DownReleased),
+    (TetrisAction::HardDrop, Action::HardDrop),
+    (TetrisAction::Hold, Action::Hold),
+    (TetrisAction::Pause, Action::Pause),
+    (TetrisAction::Restart, Action::Restart),
+];
+
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TetrisState {
+    Falling = 0,
+    Clearing,
+    Locking,
+    Paused,
+    GameOver,
+    Finished,
+}
+
+
+/// What `tetris_cells` writes for each cell.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TetrisCell {
+    Empty = 0,
+    Locked,
+    Active,
+}
+
+
+// Runs `f`, or gives `fallback` if it panics.
+fn guard<T, F: FnOnce() -> T>(fallback: T, f: F) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(fallback)
+}
+
+
+/// Starts a marathon game at `level` (0-20) with the default rules. The
+/// piece sequence depends only on `seed`. Returns null for a bad level.
+#[no_mangle]
+pub extern "C" fn tetris_new(seed: u64, level: u8) -> *mut TetrisGame {
+    if level > 20 {
+        return ptr::null_mut();
+    }
+    guard(ptr::null_mut(), || {
+        let game = TetrisGame {
+            engine: Engine::new(level, seed, Rules::default()),
+            actions: vec![],
+        };
+        Box::into_raw(Box::new(game))
+    })
+}
+
+
+/// # Safety
+///
+/// `game` must be null or come from `tetris_new`, and is gone afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn tetris_free(game: *mut TetrisGame) {
+    if !game.is_null() {
+        guard((), || drop(Box::from_raw(game)));
+    }
+}
+
+
+/// Queues `action`, one of `TetrisAction`, for the next tick. Returns
+/// false if it is not a known action.
+///
+/// # Safety
+///
+/// `game` must be null or a live game from `tetris_new`.
+#[no_mangle]
+pub unsafe extern "C" fn tetris_press(game: *mut TetrisGame, action: c_int) -> bool {
+    let action = ACTIONS.iter().find(|&&(key, _)| key as c_int == action);
+    guard(false, || match (game.as_mut(), action) {
+        (Some(game), Some(&(_, action))) => {
+            game.actions.push(action);
+            true
+        },
+        _ => false,
+    })
+}
+
+
+/// Applies the queued inputs and advances the game by one update.
+///
+/// # Safety
+///
+/// `game` must be null or a live game from `tetris_new`.
+#[no_mangle]
+pub unsafe extern "C" fn tetris_tick(game: *mut TetrisGame) {
+    if let Some(game) = game.as_mut() {
+        guard((), || {
+            game.engine.tick(&game.actions);
+            game.actions.clear();
+        });
+    }
+}
+
+
+/// Columns in the well.
+///
+/// # Safety
+///
+/// `game` must be null or a live game from `tetris_new`.
+#[no_mangle]
+pub unsafe extern "C" fn tetris_width(game: *const TetrisGame) -> c_int {
+    guard(0, || game.as_ref().map_or(0, |game| game.engine.grid().width))
+}
+
+
+/// Rows in the well.
+///
+/// # Safety
+///
+/// `game` must be null or a live game from `tetris_new`.
+#[no_mangle]
+pub unsafe extern "C" fn tetris_height(game: *const TetrisGame) -> c_int {
+    guard(0, || game.as_ref().map_or(0, |game| game.engine.grid().height))
+}
+
+
+/// Writes up to `len` `TetrisCell`s into `cells`, a row at a time from the
+/// bottom row up, and returns how many cells the well has.
+///
+/// # Safety
+///
+/// `game` must be null or a live game from `tetris_new`, and `cells` must
+/// be null or point to at least `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn tetris_cells(game: *const TetrisGame, cells: *mut u8, len: usize)
+                                      -> usize {
+    let game = match game.as_ref() {
+        Some(game) => game,
+        None => return 0,
+    };
+    guard(0, || cells_of(game, cells, len))
+}
+
+
+unsafe fn cells_of(game: &TetrisGame, cells: *mut u8, len: usize) -> usize {
+    let grid = game.engine.grid();
+    let width = grid.width as usize;
+    let mut board: Vec<u8> = grid.cells().iter()
+        .flat_map(|row| row.iter().map(|&filled| if filled {
+            TetrisCell::Locked as u8
+        } else {
+            TetrisCell::Empty as u8
+        }))
+        .collect();
+    for block in game.engine.active().blocks() {
+        if let Some(cell) = board.get_mut((block.y as usize - 1) * width + block.x as usize) {
+            *cell = TetrisCell::Active as u8;
+        }
+    }
+    if !cells.is_null() {
+        let out = slice::from_raw_parts_mut(cells, len);
+        for (out, &cell) in out.iter_mut().zip(&board) {
+            *out = cell;
+        }
+    }
+    board.len()
+}
+
+
+/// # Safety
+///
+/// `game` must be null or a live game from `tetris_new`.
+#[no_mangle]
+pub unsafe extern "C" fn tetris_score(game: *const TetrisGame) -> u32 {
+    guard(0, || game.as_ref().map_or(0, |game| game.engine.score()))
+}
+
+
+/// # Safety
+///
+/// `game` must be null or a live game from `tetris_new`.
+#[no_mangle]
+pub unsafe extern "C" fn tetris_lines(game: *const TetrisGame) -> u32 {
+    guard(0, || game.as_ref().map_or(0, |game| game.engine.lines()))
+}
+
+
+/// # Safety
+///
+/// `game` must be null or a live game from `tetris_new`.
+#[no_mangle]
+pub unsafe extern "C" fn tetris_level(game: *const TetrisGame) -> u8 {
+    guard(0, || game.as_ref().map_or(0, |game| game.engine.level()))
+}
+
+
+/// A null game is over.
+///
+/// # Safety
+///
+/// `game` must be null or a live game from `tetris_new`.
+#[no_mangle]
+pub unsafe extern "C" fn tetris_state(game: *const TetrisGame) -> TetrisState {
+    let game = match game.as_ref() {
+        Some(game) => game,
+        None => return TetrisState::GameOver,
+    };
+    guard(TetrisState::GameOver, || match *game.engine.state() {
+        States::Falling => TetrisState::Falling,
+        States::Clearing => TetrisState::Clearing,
+        States::Locking => TetrisState::Locking,
+        States::Paused(_) => TetrisState::Paused,
+        States::GameOver => TetrisState::GameOver,
+        States::Finished => TetrisState::Finished,
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_agrees_with_the_engine() {
+        assert_eq!(TETRIS_TICKS_PER_SECOND, tetris::TICKS_PER_SECOND);
+    }
+
+    #[test]
+    fn panics_stay_on_the_rust_side() {
+        assert_eq!(guard(7, || panic!("boom")), 7);
+    }
+
+    #[test]
+    fn a_hard_drop_through_the_c_abi() {
+        unsafe {
+            let game = tetris_new(3, 0);
+            assert!(!game.is_null());
+            assert!(tetris_press(game, TetrisAction::HardDrop as c_int));
+            tetris_tick(game);
+            while tetris_state(game) != TetrisState::Falling {
+                tetris_tick(game);
+            }
+            let len = tetris_cells(game, ptr::null_mut(), 0);
+            assert_eq!(len, (tetris_width(game) * tetris_height(game)) as usize);
+            let mut cells = vec![0xff; len];
+            assert_eq!(tetris_cells(game, cells.as_mut_ptr(), len), len);
+            let count = |kind: TetrisCell| cells.iter().filter(|&&cell| cell == kind as u8).count();
+            assert_eq!(count(TetrisCell::Locked), 4);
+            assert_eq!(count(TetrisCell::Active), 4);
+            assert_eq!(count(TetrisCell::Empty), len - 8);
+            assert!(tetris_score(game) > 0);
+            assert_eq!(tetris_lines(game), 0);
+            assert!(!tetris_press(game, 99));
+            tetris_free(game);
+        }
+    }
+
+    #[test]
+    fn null_games_get_the_fallbacks() {
+        unsafe {
+            let game = ptr::null_mut();
+            assert!(tetris_new(0, 21).is_null());
+            assert!(!tetris_press(game, TetrisAction::HardDrop as c_int));
+            tetris_tick(game);
+            assert_eq!(tetris_width(game), 0);
+            assert_eq!(tetris_height(game), 0);
+            assert_eq!(tetris_cells(game, ptr::null_mut(), 0), 0);
+            assert_eq!(tetris_score(game), 0);
+            assert_eq!(tetris_lines(game), 0);
+            assert_eq!(tetris_level(game), 0);
+            assert_eq!(tetris_state(game), TetrisState::GameOver);
+            tetris_free(game);
+        }
+    }
+}
@@ -0,0 +1,398 @@
+use std::cmp::{ max, min };
+use std::collections::{ HashSet, VecDeque };
+
+use engine::{ Action, Engine, States, TICKS_PER_SECOND };
+use models::{ Direction, Grid, Movement, Tetrimino };
+
+
+// How long the bot looks at a finished game before starting another.
+const RESTART_TICKS: u64 = 3 * TICKS_PER_SECOND;
+
+// The presses the bot moves pieces with, each a single move. Soft drop is
+// only needed to make room for turns.
+const MOVES: [Action; 5] = [
+    Action::Left,
+    Action::Right,
+    Action::Rotate,
+    Action::RotateCcw,
+    Action::Rotate180,
+];
+
+
+/// How much each feature of the well counts when comparing placements.
+/// Features that make the well worse get negative weights.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Weights {
+    /// The heights of all the columns added up.
+    pub height: f64,
+    pub lines: f64,
+    /// Empty cells with a block somewhere above them.
+    pub holes: f64,
+    /// How much neighbouring column heights differ.
+    pub bumpiness: f64,
+    /// How far columns sit below both of their neighbours.
+    pub wells: f64,
+}
+
+
+impl Default for Weights {
+    fn default() -> Weights {
+        Weights {
+            height: -0.51,
+            lines: 0.76,
+            holes: -0.36,
+            bumpiness: -0.18,
+            wells: -0.1,
+        }
+    }
+}
+
+
+/// Where to put a piece: how it is turned, and the column its leftmost
+/// block ends up in.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Placement {
+    pub orientation: usize,
+    pub column: i32,
+}
+
+
+/// Plays by picking the best placement for each piece and pressing the
+/// keys that get it there, one move per update.
+pub struct Bot {
+    weights: Weights,
+    // The placement being worked towards, and the well it was picked for.
+    target: Option<(Placement, (usize, u32))>,
+    // The moves still to make, and where the piece should be for them.
+    route: VecDeque<Action>,
+    expected: Option<(usize, i32, i32)>,
+    waited: u64,
+}
+
+
+impl Bot {
+    pub fn new(weights: Weights) -> Bot {
+        Bot {
+            weights,
+            target: None,
+            route: VecDeque::new(),
+            expected: None,
+            waited: 0,
+        }
+    }
+
+    /// The best placement for the active piece, if it has anywhere to go.
+    pub fn best(&self, engine: &Engine) -> Option<Placement> {
+        let mut best: Option<(f64, Placement)> = None;
+        for (placement, landed) in placements(engine.active(), engine.grid()) {
+            let score = self.evaluate(engine.grid(), &landed);
+            match best {
+                Some((best_score, _)) if best_score >= score => {},
+                _ => best = Some((score, placement)),
+            }
+        }
+        best.map(|(_, placement)| placement)
+    }
+
+    /// Rates the well after `landed` locks into it. Higher is better.
+    pub fn evaluate(&self, grid: &Grid, landed: &Tetrimino) -> f64 {
+        let mut grid = grid.clone();
+        grid.lock(landed.clone());
+        let lines = grid.clear_full_rows();
+        let heights = heights(&grid);
+        let filled = grid.blocks().len() as i32;
+
+        let height: i32 = heights.iter().sum();
+        let holes = height - filled;
+        let bumpiness: i32 = heights.windows(2)
+            .map(|pair| (pair[0] - pair[1]).abs())
+            .sum();
+        let wells: i32 = (0..heights.len())
+            .map(|x| {
+                let left = if x > 0 { heights[x - 1] } else { grid.height };
+                let right = heights.get(x + 1).cloned().unwrap_or(grid.height);
+                max(min(left, right) - heights[x], 0)
+            })
+            .sum();
+
+        self.weights.height * height as f64 +
+            self.weights.lines * lines as f64 +
+            self.weights.holes * holes as f64 +
+            self.weights.bumpiness * bumpiness as f64 +
+            self.weights.wells * wells as f64
+    }
+
+    /// What to press on the next update of `engine`.
+    pub fn actions(&mut self, engine: &Engine) -> Vec<Action> {
+        match *engine.state() {
+            States::GameOver | States::Finished => {
+                self.waited += 1;
+                if self.waited < RESTART_TICKS {
+                    return vec![];
+                }
+                self.waited = 0;
+                self.target = None;
+                return vec![Action::Restart];
+            },
+            States::Falling | States::Locking => {},
+            _ => return vec![],
+        }
+        self.waited = 0;
+
+        // Anything locking, whether the bot dropped it or not, changes
+        // the well and calls for a new plan.
+        let well = (engine.grid().blocks().len(), engine.lines());
+        let placement = match self.target {
+            Some((placement, planned_for)) if planned_for == well => placement,
+            _ => match self.best(engine) {
+                Some(placement) => {
+                    self.target = Some((placement, well));
+                    self.expected = None;
+                    placement
+                },
+                None => return self.drop(),
+            },
+        };
+
+        // Gravity or a failed move puts the piece somewhere the route did
+        // not expect, and the way there is found again from where it is.
+        let active = engine.active();
+        if self.expected != Some(position(active)) {
+            match route(active, engine.grid(), placement) {
+                Some(moves) => self.route = moves.into_iter().collect(),
+                None => {
+                    self.target = None;
+                    return vec![];
+                },
+            }
+        }
+        match self.route.pop_front() {
+            Some(key) => {
+                let mut moved = active.clone();
+                make(&mut moved, engine.grid(), key);
+                self.expected = Some(position(&moved));
+                press(key)
+            },
+            None => self.drop(),
+        }
+    }
+
+    fn drop(&mut self) -> Vec<Action> {
+        self.target = None;
+        self.route.clear();
+        self.expected = None;
+        vec![Action::HardDrop]
+    }
+}
+
+
+/// Every placement `active` can reach by sliding and turning, falling a
+/// row at a time where it has no room to turn, before dropping straight
+/// down, along with the piece where it lands.
+pub fn placements(active: &Tetrimino, grid: &Grid) -> Vec<(Placement, Tetrimino)> {
+    search(active, grid).into_iter()
+        .map(|(placement, landed, _)| (placement, landed))
+        .collect()
+}
+
+
+// Searches outwards from `active` one move at a time, so each placement
+// comes with the fewest moves that reach it.
+fn search(active: &Tetrimino, grid: &Grid) -> Vec<(Placement, Tetrimino, Vec<Action>)> {
+    // Every piece reached, with the piece and move it was reached from.
+    let mut reached: Vec<(Tetrimino, Option<(usize, Action)>)> = vec![];
+    let mut found: Vec<(Placement, Tetrimino, usize)> = vec![];
+    let mut seen = HashSet::new();
+    if grid.is_legal(&active.blocks()) {
+        seen.insert(position(active));
+        reached.push((active.clone(), None));
+    }
+    let mut next = 0;
+    while next < reached.len() {
+        let tetrimino = reached[next].0.clone();
+        // Dropping straight down keeps the orientation and column.
+        let placement = Placement {
+            orientation: tetrimino.orientation(),
+            column: left(&tetrimino),
+        };
+        if !found.iter().any(|&(seen, _, _)| seen == placement) {
+            let mut landed = tetrimino.clone();
+            while slide(&mut landed, grid, Direction::Down) {}
+            found.push((placement, landed, next));
+        }
+        // Pieces can't always turn where they are, such as classic ones
+        // that spawn touching the ceiling, but may a row further down.
+        let mut cramped = false;
+        for &key in &MOVES {
+            let mut moved = tetrimino.clone();
+            if !make(&mut moved, grid, key) {
+                cramped |= key != Action::Left && key != Action::Right;
+            } else if seen.insert(position(&moved)) {
+                reached.push((moved, Some((next, key))));
+            }
+        }
+        let mut fallen = tetrimino.clone();
+        if cramped && make(&mut fallen, grid, Action::Down) && seen.insert(position(&fallen)) {
+            reached.push((fallen, Some((next, Action::Down))));
+        }
+        next += 1;
+    }
+    found.into_iter()
+        .map(|(placement, landed, mut at)| {
+            let mut moves = vec![];
+            while let Some((from, key)) = reached[at].1 {
+                moves.push(key);
+                at = from;
+            }
+            moves.reverse();
+            (placement, landed, moves)
+        })
+        .collect()
+}
+
+
+// The fewest moves that take `active` to `placement`, if it can get there.
+fn route(active: &Tetrimino, grid: &Grid, placement: Placement) -> Option<Vec<Action>> {
+    search(active, grid).into_iter()
+        .find(|&(found, _, _)| found == placement)
+        .map(|(_, _, moves)| moves)
+}
+
+
+/// Every key that takes `active` to `placement` and drops it there, to be
+/// pressed on a single update.
+pub fn keys(active: &Tetrimino, grid: &Grid, placement: Placement) -> Vec<Action> {
+    let mut keys: Vec<Action> = route(active, grid, placement).unwrap_or_default()
+        .into_iter()
+        .flat_map(press)
+        .collect();
+    keys.push(Action::HardDrop);
+    keys
+}
+
+
+// The keys that make one move: a press, and the release for keys that
+// repeat when held.
+fn press(key: Action) -> Vec<Action> {
+    match key {
+        Action::Left => vec![Action::Left, Action::LeftReleased],
+        Action::Right => vec![Action::Right, Action::RightReleased],
+        Action::Down => vec![Action::Down, Action::DownReleased],
+        key => vec![key],
+    }
+}
+
+
+// Moves `tetrimino` the way the engine does for `key`.
+fn make(tetrimino: &mut Tetrimino, grid: &Grid, key: Action) -> bool {
+    match key {
+        Action::Left => slide(tetrimino, grid, Direction::Left),
+        Action::Right => slide(tetrimino, grid, Direction::Right),
+        Action::Down => slide(tetrimino, grid, Direction::Down),
+        Action::Rotate => tetrimino.rotate(grid),
+        Action::RotateCcw => tetrimino.rotate_ccw(grid),
+        Action::Rotate180 => tetrimino.rotate_180(grid),
+        _ => false,
+    }
+}
+
+
+fn slide(tetrimino: &mut Tetrimino, grid: &Grid, direction: Direction) -> bool {
+    grid.is_legal(&tetrimino.peek(&Movement::Shift(direction))) &&
+        tetrimino.shift(direction, grid)
+}
+
+
+fn left(tetrimino: &Tetrimino) -> i32 {
+    tetrimino.blocks().iter().map(|block| block.x).min().unwrap_or(0)
+}
+
+
+// Where a piece is: its orientation, leftmost column and lowest row.
+fn position(tetrimino: &Tetrimino) -> (usize, i32, i32) {
+    let bottom = tetrimino.blocks().iter().map(|block| block.y).min().unwrap_or(0);
+    (tetrimino.orientation(), left(tetrimino), bottom)
+}
+
+
+/// The row of the highest block in each column, or 0 for an empty one.
+fn heights(grid: &Grid) -> Vec<i32> {
+    let mut heights = vec![0; grid.width as usize];
+    for block in grid.blocks() {
+        let height = &mut heights[block.x as usize];
+        *height = max(*height, block.y);
+    }
+    heights
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use engine::Rules;
+    use models::{ RotationSystem, TetriminoType, Tetriminos };
+    use randomizer::RandomizerKind;
+
+    fn orientations(system: RotationSystem) -> Vec<usize> {
+        let tetriminos = Tetriminos::new(0, system, RandomizerKind::Bag7);
+        let t = Tetrimino::new(TetriminoType::T, &tetriminos);
+        let mut orientations: Vec<usize> = placements(&t, &Grid::new(20, 10)).iter()
+            .map(|&(placement, _)| placement.orientation)
+            .collect();
+        orientations.sort();
+        orientations.dedup();
+        orientations
+    }
+
+    // Drops `pieces` pieces and returns the lines cleared.
+    fn play(system: RotationSystem, pieces: u32) -> u32 {
+        let rules = Rules {
+            rotation: system,
+            ..Rules::default()
+        };
+        let mut engine = Engine::new(0, 7, rules);
+        let mut bot = Bot::new(Weights::default());
+        let mut dropped = 0;
+        while dropped < pieces {
+            assert!(*engine.state() != States::GameOver, "topped out");
+            let actions = bot.actions(&engine);
+            if actions.contains(&Action::HardDrop) {
+                dropped += 1;
+            }
+            engine.tick(&actions);
+        }
+        engine.lines()
+    }
+
+    #[test]
+    fn every_orientation_is_reachable() {
+        assert_eq!(orientations(RotationSystem::Srs), vec![0, 1, 2, 3]);
+        // Classic pieces can only turn once they have fallen a little.
+        assert_eq!(orientations(RotationSystem::Classic), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn keys_land_the_piece_where_placed() {
+        for &system in &[RotationSystem::Srs, RotationSystem::Classic] {
+            let rules = Rules {
+                rotation: system,
+                ..Rules::default()
+            };
+            let start = Engine::new(0, 3, rules.clone());
+            for (placement, landed) in placements(start.active(), start.grid()) {
+                let mut engine = Engine::new(0, 3, rules.clone());
+                let keys = keys(engine.active(), engine.grid(), placement);
+                engine.tick(&keys);
+                let locked = engine.grid().blocks();
+                assert!(landed.blocks().iter().all(|block| locked.contains(block)),
+                        "{:?} {:?}", system, placement);
+            }
+        }
+    }
+
+    #[test]
+    fn bot_clears_lines() {
+        assert!(play(RotationSystem::Srs, 100) >= 30);
+        assert!(play(RotationSystem::Classic, 100) >= 30);
+    }
+}
@@ -0,0 +1,1174 @@
+use std::cmp::{max, min};
+use std::collections::VecDeque;
+use std::mem;
+
+use rand::{ IsaacRng, Rng };
+
+use models::{ Block, Direction, Grid, Movement, RotationSystem, Tetrimino, TetriminoType,
+              Tetriminos };
+use randomizer::{ self, RandomizerKind };
+use scoring::{ Clear, Spin, Stats };
+
+
+/// Updates per second. Timers in game modes count in ticks of this length.
+pub const TICKS_PER_SECOND: u64 = 60;
+
+/// How many upcoming pieces the engine keeps visible.
+pub const MAX_PREVIEW: usize = 6;
+
+// How long the name of a special clear stays up.
+const LABEL_TICKS: u8 = 120;
+
+// Moves a grounded piece gets under `LockReset::Move` before it locks on
+// contact.
+const MAX_LOCK_RESETS: u8 = 15;
+
+const NEIGHBOURS: [(i32, i32); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+
+
+#[derive(Clone, Eq, PartialEq)]
+pub enum States {
+    Falling,
+    Clearing,
+    Locking,
+    Paused(Box<States>),
+    GameOver,
+    /// The game mode's goal was reached.
+    Finished,
+}
+
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Action {
+    Rotate,
+    RotateCcw,
+    Rotate180,
+    Left,
+    LeftReleased,
+    Right,
+    RightReleased,
+    Down,
+    DownReleased,
+    HardDrop,
+    Hold,
+    Pause,
+    Restart,
+}
+
+
+impl Action {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Action::Rotate => "Rotate",
+            Action::RotateCcw => "RotateCcw",
+            Action::Rotate180 => "Rotate180",
+            Action::Left => "Left",
+            Action::LeftReleased => "LeftReleased",
+            Action::Right => "Right",
+            Action::RightReleased => "RightReleased",
+            Action::Down => "Down",
+            Action::DownReleased => "DownReleased",
+            Action::HardDrop => "HardDrop",
+            Action::Hold => "Hold",
+            Action::Pause => "Pause",
+            Action::Restart => "Restart",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Action> {
+        match name {
+            "Rotate" => Some(Action::Rotate),
+            "RotateCcw" => Some(Action::RotateCcw),
+            "Rotate180" => Some(Action::Rotate180),
+            "Left" => Some(Action::Left),
+            "LeftReleased" => Some(Action::LeftReleased),
+            "Right" => Some(Action::Right),
+            "RightReleased" => Some(Action::RightReleased),
+            "Down" => Some(Action::Down),
+            "DownReleased" => Some(Action::DownReleased),
+            "HardDrop" => Some(Action::HardDrop),
+            "Hold" => Some(Action::Hold),
+            "Pause" => Some(Action::Pause),
+            "Restart" => Some(Action::Restart),
+            _ => None,
+        }
+    }
+}
+
+
+/// How a held key repeats, in update ticks: `delay` before the first
+/// repeat, then one every `rate`. A rate of 0 repeats all the way at once.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct AutoRepeat {
+    pub delay: u8,
+    pub rate: u8,
+}
+
+
+impl AutoRepeat {
+    /// Whether a key held for `held` ticks repeats on this one.
+    pub fn fires(&self, held: u32) -> bool {
+        let delay = self.delay as u32;
+        // A rate of 0 has no remainder, and repeats every tick.
+        held >= delay && matches!((held - delay).checked_rem(self.rate as u32), None | Some(0))
+    }
+}
+
+
+/// What puts a grounded piece's lock delay back to full.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LockReset {
+    /// Nothing. The delay runs down across the whole piece.
+    Classic,
+    /// Dropping down a row.
+    Step,
+    /// Any move or rotation, up to `MAX_LOCK_RESETS` on the ground per row
+    /// reached.
+    Move,
+}
+
+
+impl LockReset {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            LockReset::Classic => "classic",
+            LockReset::Step => "step",
+            LockReset::Move => "move",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<LockReset> {
+        match name {
+            "classic" => Some(LockReset::Classic),
+            "step" => Some(LockReset::Step),
+            "move" => Some(LockReset::Move),
+            _ => None,
+        }
+    }
+}
+
+
+/// What a game is played for, and what ends it besides topping out.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Mode {
+    /// Endless play.
+    Marathon,
+    /// Clear this many lines as fast as possible.
+    Sprint(u32),
+    /// Score as much as possible in this many ticks.
+    Ultra(u64),
+    /// Dig down through garbage rows.
+    Dig(Dig),
+    /// Line clears attack an opponent, whose attacks arrive as garbage.
+    Versus,
+}
+
+
+/// How a dig game starts and how its garbage keeps coming.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Dig {
+    pub rows: u32,
+    pub rise: Rise,
+}
+
+
+/// When another garbage row is pushed in. Rows wait for a piece to lock
+/// without clearing anything before they rise.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Rise {
+    Never,
+    Ticks(u64),
+    Pieces(u32),
+}
+
+
+impl Mode {
+    pub fn describe(&self) -> String {
+        match *self {
+            Mode::Marathon => "marathon".to_string(),
+            Mode::Sprint(lines) => format!("sprint/{}", lines),
+            Mode::Ultra(ticks) => format!("ultra/{}", ticks),
+            Mode::Dig(dig) => {
+                let rise = match dig.rise {
+                    Rise::Never => "never".to_string(),
+                    Rise::Ticks(ticks) => format!("ticks:{}", ticks),
+                    Rise::Pieces(pieces) => format!("pieces:{}", pieces),
+                };
+                format!("dig/{}/{}", dig.rows, rise)
+            },
+            Mode::Versus => "versus".to_string(),
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Mode> {
+        let mut parts = value.splitn(3, '/');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some("marathon"), None, None) => Some(Mode::Marathon),
+            (Some("versus"), None, None) => Some(Mode::Versus),
+            (Some("sprint"), Some(lines), None) => lines.parse().ok().map(Mode::Sprint),
+            (Some("ultra"), Some(ticks), None) => ticks.parse().ok().map(Mode::Ultra),
+            (Some("dig"), Some(rows), Some(rise)) => {
+                let rows = rows.parse().ok()?;
+                let mut rise_parts = rise.splitn(2, ':');
+                let rise = match (rise_parts.next(), rise_parts.next()) {
+                    (Some("never"), None) => Rise::Never,
+                    (Some("ticks"), Some(ticks)) => Rise::Ticks(ticks.parse().ok()?),
+                    (Some("pieces"), Some(pieces)) => Rise::Pieces(pieces.parse().ok()?),
+                    _ => return None,
+                };
+                Some(Mode::Dig(Dig { rows, rise }))
+            },
+            _ => None,
+        }
+    }
+}
+
+
+/// Choices that change how a game plays out. Replays store these so a game
+/// can be played back under the rules it was recorded with.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Rules {
+    pub mode: Mode,
+    pub rotation: RotationSystem,
+    pub randomizer: RandomizerKind,
+    /// Auto shift for held Left and Right. `None` moves once per press.
+    pub shift: Option<AutoRepeat>,
+    /// After `delay`, a held soft drop pulls gravity down to `rate`.
+    pub soft_drop: AutoRepeat,
+    /// Ticks a grounded piece waits before it locks.
+    pub lock_delay: u8,
+    pub lock_reset: LockReset,
+}
+
+
+impl Default for Rules {
+    fn default() -> Rules {
+        Rules {
+            mode: Mode::Marathon,
+            rotation: RotationSystem::Srs,
+            randomizer: RandomizerKind::Bag7,
+            shift: Some(AutoRepeat {
+                delay: 10,
+                rate: 2,
+            }),
+            soft_drop: AutoRepeat {
+                delay: 0,
+                rate: 2,
+            },
+            lock_delay: 30,
+            lock_reset: LockReset::Move,
+        }
+    }
+}
+
+
+/// Game rules and state with no rendering attached. Advanced one update
+/// at a time through `tick`.
+pub struct Engine {
+    rules: Rules,
+    grid: Grid,
+    tetriminos: Tetriminos,
+    active: Tetrimino,
+    peeked: Vec<Tetrimino>,
+    held: Option<Tetrimino>,
+    can_hold: bool,
+    soft_drop: bool,
+    down_held: u32,
+    left_held: bool,
+    right_held: bool,
+    shift: Option<Direction>,
+    shift_held: u32,
+    rotated: bool,
+    spin: Spin,
+    last_clear: Option<Clear>,
+    label_ticks: u8,
+    banner_ticks: u8,
+    combo: Option<u32>,
+    back_to_back: Option<u32>,
+    stats: Stats,
+    garbage_rng: IsaacRng,
+    // Garbage waiting to rise, in batches that share a hole.
+    pending_garbage: VecDeque<u32>,
+    rise_count: u64,
+    outgoing: u32,
+    state: States,
+    score: u32,
+    default_level: u8,
+    level: u8,
+    fall_ticks: u8,
+    lock_ticks: u8,
+    lock_resets: u8,
+    lowest: i32,
+    clear_ticks: u8,
+    lines: u32,
+    clock: u64,
+    ticks: u64,
+}
+
+
+impl Engine {
+    pub fn new(start_level: u8, seed: u64, rules: Rules) -> Engine {
+        let mut tetriminos = Tetriminos::new(seed, rules.rotation, rules.randomizer);
+        let active = tetriminos.next().unwrap();
+        let peeked = tetriminos.peek_n(MAX_PREVIEW);
+        let lock_delay = rules.lock_delay;
+        let lowest = bottom(&active);
+        let mut engine = Engine {
+            rules,
+            grid: Grid::new(20, 10),
+            tetriminos,
+            active,
+            peeked,
+            held: None,
+            can_hold: true,
+            soft_drop: false,
+            down_held: 0,
+            left_held: false,
+            right_held: false,
+            shift: None,
+            shift_held: 0,
+            rotated: false,
+            spin: Spin::None,
+            last_clear: None,
+            label_ticks: 0,
+            banner_ticks: 0,
+            combo: None,
+            back_to_back: None,
+            stats: Stats::default(),
+            garbage_rng: randomizer::seeded(!seed),
+            pending_garbage: VecDeque::new(),
+            rise_count: 0,
+            outgoing: 0,
+            default_level: start_level,
+            level: start_level,
+            fall_ticks: 53,
+            lock_ticks: lock_delay,
+            lock_resets: 0,
+            lowest,
+            clear_ticks: 48,
+            score: 0,
+            lines: 0,
+            clock: 0,
+            ticks: 0,
+            state: States::Falling,
+        };
+        engine.start_garbage();
+        engine
+    }
+
+    pub fn grid(&self) -> &Grid {
+        &self.grid
+    }
+
+    pub fn active(&self) -> &Tetrimino {
+        &self.active
+    }
+
+    /// The next `MAX_PREVIEW` pieces, soonest first.
+    pub fn peeked(&self) -> &[Tetrimino] {
+        &self.peeked
+    }
+
+    pub fn held(&self) -> Option<&Tetrimino> {
+        self.held.as_ref()
+    }
+
+    /// Whether the active piece may still be swapped into the hold slot.
+    pub fn can_hold(&self) -> bool {
+        self.can_hold
+    }
+
+    pub fn state(&self) -> &States {
+        &self.state
+    }
+
+    pub fn score(&self) -> u32 {
+        self.score
+    }
+
+    pub fn level(&self) -> u8 {
+        self.level
+    }
+
+    pub fn lines(&self) -> u32 {
+        self.lines
+    }
+
+    pub fn clear_ticks(&self) -> u8 {
+        self.clear_ticks
+    }
+
+    /// Clears in a row after the first, while the streak is alive.
+    pub fn combo(&self) -> u32 {
+        self.combo.unwrap_or(0)
+    }
+
+    /// Back-to-back difficult clears in the current streak.
+    pub fn back_to_back(&self) -> u32 {
+        self.back_to_back.unwrap_or(0)
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// The last clear worth naming, until its label times out.
+    pub fn clear_label(&self) -> Option<String> {
+        match self.last_clear {
+            Some(ref clear) if self.label_ticks > 0 => clear.label(),
+            _ => None,
+        }
+    }
+
+    /// Whether a perfect clear banner should still be showing.
+    pub fn perfect_clear(&self) -> bool {
+        self.banner_ticks > 0
+    }
+
+    /// Blocks of the active piece at the row it would land on.
+    pub fn ghost(&self) -> Vec<Block> {
+        let (landed, _) = self.landing();
+        landed.blocks()
+    }
+
+    /// The game mode from the rules the engine was started with.
+    pub fn mode(&self) -> Mode {
+        self.rules.mode
+    }
+
+    /// Time played in milliseconds, not counting pauses.
+    pub fn time_ms(&self) -> u64 {
+        self.clock * 1000 / TICKS_PER_SECOND
+    }
+
+    /// Time left in milliseconds, for modes with a time limit.
+    pub fn time_left_ms(&self) -> Option<u64> {
+        match self.rules.mode {
+            Mode::Ultra(limit) => Some(limit.saturating_sub(self.clock) * 1000 / TICKS_PER_SECOND),
+            _ => None,
+        }
+    }
+
+    /// Garbage rows waiting to rise.
+    pub fn incoming_garbage(&self) -> u32 {
+        self.pending_garbage.iter().sum()
+    }
+
+    /// Queues garbage from an opponent. It rises under the next piece that
+    /// locks without clearing lines, unless line clears cancel it first.
+    pub fn receive_garbage(&mut self, rows: u32) {
+        if rows > 0 {
+            self.pending_garbage.push_back(rows);
+        }
+    }
+
+    /// Garbage rows sent since the last call, for the opponent.
+    pub fn take_attack(&mut self) -> u32 {
+        mem::replace(&mut self.outgoing, 0)
+    }
+
+    /// Number of updates run since the engine was created.
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// Applies `actions` in order, then advances the game by one update.
+    pub fn tick(&mut self, actions: &[Action]) {
+        for &action in actions {
+            self.on_action(action);
+        }
+        self.on_update();
+        self.ticks += 1;
+    }
+
+    fn pause(&mut self) {
+        let curr_state = self.state.clone();
+        self.state = States::Paused(Box::new(curr_state));
+    }
+
+    fn resume(&mut self, prev_state: States) {
+        self.state = prev_state;
+    }
+
+    fn on_move(&mut self, movement: Movement) -> bool {
+        match self.state {
+            States::Falling | States::Locking => {
+                let moved = match movement {
+                    Movement::Rotate => self.active.rotate(&self.grid),
+                    Movement::RotateCcw => self.active.rotate_ccw(&self.grid),
+                    Movement::Rotate180 => self.active.rotate_180(&self.grid),
+                    Movement::Shift(direction) => {
+                        self.grid.is_legal(&self.active.peek(&movement)) &&
+                            self.active.shift(direction, &self.grid)
+                    },
+                };
+                if !moved {
+                    return false;
+                }
+                self.rotated = !matches!(movement, Movement::Shift(_));
+                self.on_lock_reset();
+                let has_landed = self.grid.has_landed(&self.active);
+                if has_landed {
+                    self.state = States::Locking;
+                } else {
+                    self.state = States::Falling;
+                }
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// Applies the lock reset policy after the active piece has moved.
+    fn on_lock_reset(&mut self) {
+        let row = bottom(&self.active);
+        let stepped = row < self.lowest;
+        if stepped {
+            self.lowest = row;
+            self.lock_resets = 0;
+        }
+        match self.rules.lock_reset {
+            LockReset::Classic => {},
+            LockReset::Step => {
+                if stepped {
+                    self.lock_ticks = self.rules.lock_delay;
+                }
+            },
+            LockReset::Move => {
+                if stepped {
+                    self.lock_ticks = self.rules.lock_delay;
+                } else if self.state == States::Locking &&
+                    self.lock_resets < MAX_LOCK_RESETS {
+                    self.lock_resets += 1;
+                    self.lock_ticks = self.rules.lock_delay;
+                }
+            },
+        }
+    }
+
+    fn soft_drop(&mut self) {
+        if self.soft_drop {
+            return;
+        }
+        self.soft_drop = true;
+        self.down_held = 0;
+        if self.on_move(Movement::Shift(Direction::Down)) {
+            self.score += 1;
+        }
+    }
+
+    fn hard_drop(&mut self) {
+        match self.state {
+            States::Falling | States::Locking => {},
+            _ => return,
+        }
+        let (landed, rows) = self.landing();
+        self.active = landed;
+        if rows > 0 {
+            self.rotated = false;
+        }
+        self.score += 2 * rows;
+        self.lock_active();
+    }
+
+    fn landing(&self) -> (Tetrimino, u32) {
+        let mut landed = self.active.clone();
+        let mut rows = 0;
+        while self.grid.is_legal(&landed.peek(&Movement::Shift(Direction::Down))) {
+            landed.shift(Direction::Down, &self.grid);
+            rows += 1;
+        }
+        (landed, rows)
+    }
+
+    // A T spin needs the T's last move to be a rotation and three of the four
+    // cells diagonal to its center to be filled. It is only a mini unless
+    // both cells on the side the T points to are filled, or the rotation
+    // needed the last kick in the table.
+    fn detect_spin(&self) -> Spin {
+        if self.active.shape() != TetriminoType::T || !self.rotated {
+            return Spin::None;
+        }
+        let blocks = self.active.blocks();
+        let has = |x: i32, y: i32| blocks.iter().any(|block| block.x == x && block.y == y);
+        let center = blocks.iter().find(|block| {
+            NEIGHBOURS.iter()
+                .filter(|&&(dx, dy)| has(block.x + dx, block.y + dy))
+                .count() == 3
+        });
+        let (x, y) = match center {
+            Some(block) => (block.x, block.y),
+            None => return Spin::None,
+        };
+        let corners = [(x - 1, y + 1), (x + 1, y + 1), (x - 1, y - 1), (x + 1, y - 1)];
+        let filled = corners.iter()
+            .filter(|&&(cx, cy)| self.grid.is_filled(cx, cy))
+            .count();
+        if filled < 3 {
+            return Spin::None;
+        }
+        let (dx, dy) = *NEIGHBOURS.iter()
+            .find(|&&(dx, dy)| !has(x - dx, y - dy))
+            .unwrap();
+        let front = if dx == 0 {
+            [(x - 1, y + dy), (x + 1, y + dy)]
+        } else {
+            [(x + dx, y - 1), (x + dx, y + 1)]
+        };
+        let front_filled = front.iter().all(|&(cx, cy)| self.grid.is_filled(cx, cy));
+        if front_filled || self.active.last_kick() == 4 {
+            Spin::Full
+        } else {
+            Spin::Mini
+        }
+    }
+
+    fn lock_active(&mut self) {
+        self.spin = self.detect_spin();
+        self.rotated = false;
+        let mut other = self.tetriminos.next().unwrap();
+        let peeked = self.tetriminos.peek_n(MAX_PREVIEW);
+        mem::swap(&mut other, &mut self.active);
+        self.grid.lock(other);
+        self.peeked = peeked;
+        self.count_rise(false);
+        self.can_hold = true;
+        self.state = States::Clearing;
+        self.reset_lock_ticks();
+        self.reset_fall_ticks();
+    }
+
+    fn hold(&mut self) {
+        match self.state {
+            States::Falling | States::Locking if self.can_hold => {},
+            _ => return,
+        }
+        let next = match self.held.take() {
+            Some(held) => held,
+            None => {
+                let next = self.tetriminos.next().unwrap();
+                self.peeked = self.tetriminos.peek_n(MAX_PREVIEW);
+                next
+            },
+        };
+        let mut prev = mem::replace(&mut self.active, next);
+        prev.reset();
+        self.held = Some(prev);
+        self.can_hold = false;
+        self.rotated = false;
+        self.state = States::Falling;
+        self.reset_lock_ticks();
+        self.reset_fall_ticks();
+    }
+
+    /// Starts a shift. Key repeats from the OS arrive as more presses with
+    /// no release between them, and must not restart the auto-repeat.
+    fn press_shift(&mut self, direction: Direction) {
+        let held = match direction {
+            Direction::Left => self.left_held,
+            Direction::Right => self.right_held,
+            _ => false,
+        };
+        if held {
+            return;
+        }
+        match direction {
+            Direction::Left => self.left_held = true,
+            Direction::Right => self.right_held = true,
+            _ => {},
+        }
+        self.shift = Some(direction);
+        self.shift_held = 0;
+        self.on_move(Movement::Shift(direction));
+    }
+
+    /// Lets go of a shift key, falling back to the other one if it is
+    /// still held.
+    fn release_shift(&mut self, direction: Direction) {
+        match direction {
+            Direction::Left => self.left_held = false,
+            Direction::Right => self.right_held = false,
+            _ => {},
+        }
+        if self.shift != Some(direction) {
+            return;
+        }
+        self.shift = match direction {
+            Direction::Left if self.right_held => Some(Direction::Right),
+            Direction::Right if self.left_held => Some(Direction::Left),
+            _ => None,
+        };
+        self.shift_held = 0;
+    }
+
+    fn auto_shift(&mut self) {
+        let (repeat, direction) = match (self.rules.shift, self.shift) {
+            (Some(repeat), Some(direction)) => (repeat, direction),
+            _ => return,
+        };
+        self.shift_held += 1;
+        if !repeat.fires(self.shift_held) {
+            return;
+        }
+        if repeat.rate == 0 {
+            while self.on_move(Movement::Shift(direction)) {}
+        } else {
+            self.on_move(Movement::Shift(direction));
+        }
+    }
+
+    fn on_action(&mut self, action: Action) {
+        match action {
+            Action::DownReleased => {
+                self.soft_drop = false;
+                return;
+            },
+            Action::LeftReleased => {
+                self.release_shift(Direction::Left);
+                return;
+            },
+            Action::RightReleased => {
+                self.release_shift(Direction::Right);
+                return;
+            },
+            _ => {},
+        }
+        let state = self.state.clone();
+        match state {
+            States::Paused(prev_state) => {
+                match action {
+                    Action::Pause => self.resume(prev_state.as_ref().clone()),
+                    _ => {},
+                }
+            },
+            States::GameOver | States::Finished => {
+                match action {
+                    Action::Restart => self.restart(),
+                    _ => {},
+                }
+            }
+            _ => {
+                match action {
+                    Action::Pause => self.pause(),
+                    Action::Rotate => { self.on_move(Movement::Rotate); },
+                    Action::RotateCcw => { self.on_move(Movement::RotateCcw); },
+                    Action::Rotate180 => { self.on_move(Movement::Rotate180); },
+                    Action::Down => self.soft_drop(),
+                    Action::Left => self.press_shift(Direction::Left),
+                    Action::Right => self.press_shift(Direction::Right),
+                    Action::HardDrop => self.hard_drop(),
+                    Action::Hold => self.hold(),
+                    _ => {},
+                }
+            },
+        }
+    }
+
+    fn on_update(&mut self) {
+        if self.state != States::Finished && !self.grid.is_legal(&self.active.blocks()) {
+            self.state = States::GameOver;
+        }
+
+        match self.state {
+            States::GameOver | States::Finished | States::Paused(_) => {},
+            _ => {
+                self.clock += 1;
+                self.count_rise(true);
+                self.check_goal();
+                self.label_ticks = self.label_ticks.saturating_sub(1);
+                self.banner_ticks = self.banner_ticks.saturating_sub(1);
+                if self.soft_drop {
+                    self.down_held += 1;
+                }
+                self.auto_shift();
+            },
+        }
+
+        match self.state {
+            States::GameOver => {},
+            States::Finished => {},
+            States::Paused(_) => {},
+            States::Locking => {
+                let ticks = self.lock_ticks;
+                let out_of_resets = self.rules.lock_reset == LockReset::Move &&
+                    self.lock_resets >= MAX_LOCK_RESETS;
+                if ticks > 0 && !out_of_resets {
+                    self.lock_ticks -= 1;
+                } else {
+                    self.lock_active();
+                }
+            },
+            States::Clearing => {
+                let ticks = self.clear_ticks;
+                let num_full_rows = self.grid.get_full_rows().len();
+                if num_full_rows == 0 {
+                    self.update_score(0);
+                    self.state = States::Falling;
+                    self.raise_garbage();
+                } else if ticks > 0 {
+                    self.clear_ticks -= 1;
+                } else {
+                    let cleared = self.grid.clear_full_rows();
+                    self.update_score(cleared);
+                    self.lines += cleared;
+                    self.state = States::Falling;
+                    self.update_level();
+                    self.reset_clear_ticks();
+                    self.check_goal();
+                }
+            },
+            States::Falling => {
+                let soft_drop = self.rules.soft_drop;
+                if self.soft_drop && self.down_held >= soft_drop.delay as u32 {
+                    self.fall_ticks = min(self.fall_ticks, soft_drop.rate);
+                }
+                let ticks = self.fall_ticks;
+                if ticks > 0 {
+                    self.fall_ticks -= 1;
+                } else if self.grid.has_landed(&self.active) {
+                    self.state = States::Locking;
+                } else {
+                    let moved = self.on_move(Movement::Shift(Direction::Down));
+                    if moved && self.soft_drop {
+                        self.score += 1;
+                    }
+                    self.reset_fall_ticks();
+                }
+            },
+        }
+    }
+
+    fn start_garbage(&mut self) {
+        if let Mode::Dig(dig) = self.rules.mode {
+            self.pending_garbage.extend((0..dig.rows).map(|_| 1));
+            self.raise_garbage();
+        }
+    }
+
+    /// Counts a tick, or a locked piece, towards the next rising garbage row.
+    fn count_rise(&mut self, tick: bool) {
+        let every = match self.rules.mode {
+            Mode::Dig(Dig { rise: Rise::Ticks(ticks), .. }) if tick => ticks,
+            Mode::Dig(Dig { rise: Rise::Pieces(pieces), .. }) if !tick => pieces as u64,
+            _ => return,
+        };
+        self.rise_count += 1;
+        if self.rise_count >= every {
+            self.rise_count = 0;
+            self.pending_garbage.push_back(1);
+        }
+    }
+
+    /// Pushes in the garbage rows that are waiting, a hole per batch.
+    fn raise_garbage(&mut self) {
+        while let Some(rows) = self.pending_garbage.pop_front() {
+            let hole = self.garbage_rng.gen_range(0, self.grid.width as u32) as i32;
+            if !self.grid.insert_garbage(rows, hole) {
+                self.state = States::GameOver;
+            }
+        }
+    }
+
+    fn check_goal(&mut self) {
+        match self.rules.mode {
+            Mode::Sprint(target) if self.lines >= target => {
+                self.state = States::Finished;
+            },
+            Mode::Ultra(limit) if self.clock >= limit => {
+                self.state = States::Finished;
+            },
+            _ => {},
+        }
+    }
+
+    fn update_level(&mut self) {
+        let lines = self.lines;
+        self.level = max(self.level, min(lines / 10, 20) as u8);
+    }
+
+    fn update_score(&mut self, num_rows_cleared: u32) {
+        let l = (self.level + 1) as u32;
+        let mut clear = Clear {
+            lines: num_rows_cleared,
+            spin: self.spin,
+            combo: None,
+            back_to_back: false,
+            perfect: num_rows_cleared > 0 && self.grid.is_empty(),
+        };
+        if clear.lines > 0 {
+            self.combo = Some(self.combo.map_or(0, |combo| combo + 1));
+            clear.combo = self.combo;
+        } else {
+            self.combo = None;
+        }
+        if clear.is_difficult() {
+            clear.back_to_back = self.back_to_back.is_some();
+            self.back_to_back = Some(self.back_to_back.map_or(0, |chain| chain + 1));
+        } else if clear.lines > 0 {
+            self.back_to_back = None;
+        }
+        if self.rules.mode == Mode::Versus {
+            self.attack(clear.attack());
+        }
+        self.update_stats(&clear);
+        self.score += clear.points() * l;
+        if clear.perfect {
+            self.banner_ticks = LABEL_TICKS;
+        }
+        if clear.label().is_some() {
+            self.last_clear = Some(clear);
+            self.label_ticks = LABEL_TICKS;
+        }
+        self.spin = Spin::None;
+    }
+
+    /// Cancels waiting garbage with an attack, then sends what is left.
+    fn attack(&mut self, mut rows: u32) {
+        while rows > 0 {
+            let batch = match self.pending_garbage.pop_front() {
+                Some(batch) => batch,
+                None => break,
+            };
+            if batch > rows {
+                self.pending_garbage.push_front(batch - rows);
+                rows = 0;
+            } else {
+                rows -= batch;
+            }
+        }
+        self.outgoing += rows;
+        self.stats.garbage_sent += rows;
+    }
+
+    fn update_stats(&mut self, clear: &Clear) {
+        self.stats.max_combo = max(self.stats.max_combo, self.combo());
+        if clear.back_to_back {
+            self.stats.back_to_backs += 1;
+        }
+        self.stats.max_back_to_back = max(self.stats.max_back_to_back,
+                                          self.back_to_back());
+        if clear.perfect {
+            self.stats.perfect_clears += 1;
+        }
+    }
+
+    fn restart(&mut self) {
+        let active = self.tetriminos.next().unwrap();
+        let peeked = self.tetriminos.peek_n(MAX_PREVIEW);
+        self.active = active;
+        self.peeked = peeked;
+        self.held = None;
+        self.can_hold = true;
+        self.rotated = false;
+        self.spin = Spin::None;
+        self.last_clear = None;
+        self.label_ticks = 0;
+        self.banner_ticks = 0;
+        self.combo = None;
+        self.back_to_back = None;
+        self.stats = Stats::default();
+        self.grid = Grid::new(20, 10);
+        self.level = self.default_level;
+        self.fall_ticks = 53;
+        self.reset_lock_ticks();
+        self.clear_ticks = 48;
+        self.score = 0;
+        self.lines = 0;
+        self.clock = 0;
+        self.pending_garbage.clear();
+        self.rise_count = 0;
+        self.outgoing = 0;
+        self.state = States::Falling;
+        self.start_garbage();
+    }
+
+    fn reset_fall_ticks(&mut self) {
+        self.fall_ticks = match self.level {
+            0 => 53,
+            1 => 49,
+            2 => 45,
+            3 => 41,
+            4 => 37,
+            5 => 33,
+            6 => 28,
+            7 => 22,
+            8 => 17,
+            9 => 11,
+            10 => 10,
+            11 => 9,
+            12 => 8,
+            13 => 7,
+            14 => 6,
+            15 => 6,
+            16 => 5,
+            17 => 5,
+            18 => 4,
+            19 => 4,
+            20 => 3,
+            _ => panic!("illegal level"),
+        }
+    }
+
+    /// Gives the active piece a fresh lock delay and reset count.
+    fn reset_lock_ticks(&mut self) {
+        self.lock_ticks = self.rules.lock_delay;
+        self.lock_resets = 0;
+        self.lowest = bottom(&self.active);
+    }
+
+    fn reset_clear_ticks(&mut self) {
+        self.clear_ticks = 93;
+    }
+}
+
+
+// The lowest row any block of the piece sits on.
+fn bottom(tetrimino: &Tetrimino) -> i32 {
+    tetrimino.blocks().iter().map(|block| block.y).min().unwrap()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_and_inputs_play_the_same() {
+        let play = || {
+            let mut engine = Engine::new(5, 42, Rules::default());
+            for tick in 0..20000 {
+                let actions = match tick % 17 {
+                    0 => vec![Action::Left, Action::Rotate],
+                    4 => vec![Action::LeftReleased],
+                    6 => vec![Action::Right],
+                    9 => vec![Action::RightReleased, Action::RotateCcw],
+                    12 => vec![Action::HardDrop],
+                    _ => vec![],
+                };
+                engine.tick(&actions);
+                if *engine.state() == States::GameOver {
+                    break;
+                }
+            }
+            engine
+        };
+        let (first, second) = (play(), play());
+        assert!(!first.grid().is_empty());
+        assert_eq!(first.grid().blocks(), second.grid().blocks());
+        assert_eq!(first.active().blocks(), second.active().blocks());
+        assert_eq!(first.score(), second.score());
+        assert_eq!(first.lines(), second.lines());
+        assert_eq!(first.ticks(), second.ticks());
+        assert!(first.state() == second.state());
+    }
+
+    #[test]
+    fn repeated_presses_do_not_restart_auto_repeat() {
+        let mut engine = Engine::new(0, 1, Rules::default());
+        let column = |engine: &Engine| {
+            engine.active().blocks().iter().map(|block| block.x).min().unwrap()
+        };
+        let start = column(&engine);
+        // Pressed, then repeated by the OS every few updates while held.
+        for tick in 0..12 {
+            let actions = if tick % 3 == 0 { vec![Action::Left] } else { vec![] };
+            engine.tick(&actions);
+            if tick < 9 {
+                assert_eq!(column(&engine), start - 1);
+            }
+        }
+        assert_eq!(column(&engine), start - 3);
+        engine.tick(&[Action::LeftReleased, Action::Right]);
+        assert_eq!(column(&engine), start - 2);
+    }
+
+    #[test]
+    fn srs_kicks_i_off_the_wall() {
+        let mut engine = Engine::new(0, 1, Rules::default());
+        engine.active = Tetrimino::new(TetriminoType::I, &engine.tetriminos);
+        // Standing up against the left wall.
+        engine.active.place(-2, 20, 1);
+        assert!(engine.grid.is_legal(&engine.active.blocks()));
+        engine.tick(&[Action::Rotate]);
+        let blocks = engine.active().blocks();
+        let columns: Vec<i32> = blocks.iter().map(|block| block.x).collect();
+        assert_eq!(columns, vec![0, 1, 2, 3]);
+        assert!(blocks.iter().all(|block| block.y == blocks[0].y));
+    }
+
+    #[test]
+    fn classic_rotation_does_not_kick() {
+        let rules = Rules { rotation: RotationSystem::Classic, ..Rules::default() };
+        let mut engine = Engine::new(0, 1, rules);
+        engine.active = Tetrimino::new(TetriminoType::I, &engine.tetriminos);
+        engine.active.place(-1, 20, 1);
+        let against_wall = engine.active().blocks();
+        assert!(against_wall.iter().all(|block| block.x == 0));
+        engine.tick(&[Action::Rotate]);
+        assert_eq!(engine.active().blocks(), against_wall);
+    }
+
+    // A well with a T-spin double slot at columns 3-5, and a T in it
+    // upside down, resting and about to lock.
+    fn t_slot() -> Engine {
+        let mut engine = Engine::new(0, 1, Rules::default());
+        let mut grid = Grid::new(20, 10);
+        for x in (0..10).filter(|&x| x != 4) {
+            grid.fill(x, 1);
+        }
+        for x in (0..10).filter(|&x| !(3..=5).contains(&x)) {
+            grid.fill(x, 2);
+        }
+        grid.fill(3, 3);
+        engine.grid = grid;
+        let mut t = Tetrimino::new(TetriminoType::T, &engine.tetriminos);
+        t.place(3, 3, 2);
+        engine.active = t;
+        engine.state = States::Locking;
+        engine.lock_ticks = 0;
+        engine
+    }
+
+    fn run(engine: &mut Engine, ticks: usize) {
+        for _ in 0..ticks {
+            engine.tick(&[]);
+        }
+    }
+
+    #[test]
+    fn t_spin_double() {
+        let mut engine = t_slot();
+        assert!(engine.grid.is_legal(&engine.active.blocks()));
+        engine.rotated = true;
+        assert_eq!(engine.detect_spin(), Spin::Full);
+        run(&mut engine, 200);
+        assert_eq!(engine.lines(), 2);
+        assert_eq!(engine.score(), 1200);
+        assert_eq!(engine.last_clear.unwrap().label().unwrap(), "T-SPIN DOUBLE");
+    }
+
+    #[test]
+    fn no_t_spin_without_a_rotation() {
+        let mut engine = t_slot();
+        engine.rotated = false;
+        assert_eq!(engine.detect_spin(), Spin::None);
+        run(&mut engine, 200);
+        assert_eq!(engine.lines(), 2);
+        assert_eq!(engine.score(), 100);
+    }
+
+    #[test]
+    fn t_spin_mini() {
+        let mut engine = Engine::new(0, 1, Rules::default());
+        let mut grid = Grid::new(20, 10);
+        grid.fill(1, 3);
+        engine.grid = grid;
+        let mut t = Tetrimino::new(TetriminoType::T, &engine.tetriminos);
+        // Pointing right from the wall, under a block.
+        t.place(-1, 3, 1);
+        engine.active = t;
+        assert!(engine.grid.is_legal(&engine.active.blocks()));
+        engine.rotated = true;
+        assert_eq!(engine.detect_spin(), Spin::Mini);
+    }
+}
@@ -0,0 +1,239 @@
+use bot::{ self, Placement };
+use engine::{ Action, Engine, Rules, States };
+use models::TetriminoType;
+
+
+/// What an agent does on one step.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EnvAction {
+    /// Let one update pass without pressing anything.
+    Wait,
+    /// Press a key and let one update pass.
+    Key(Action),
+    /// Turn and slide the active piece into place and hard drop it, then
+    /// let updates pass until the next piece is in play.
+    Place(Placement),
+}
+
+
+/// What the agent sees of the game.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Observation {
+    /// Locked blocks, as rows of columns with the bottom row first.
+    pub cells: Vec<Vec<bool>>,
+    pub active: TetriminoType,
+    /// Clockwise quarter turns of the active piece from how it spawned.
+    pub orientation: usize,
+    /// Cells of the active piece as `(column, row)`, counting rows from
+    /// the bottom like `cells`.
+    pub piece: Vec<(i32, i32)>,
+    pub next: Vec<TetriminoType>,
+    pub hold: Option<TetriminoType>,
+}
+
+
+/// What a step led to. The reward is the points it scored.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Step {
+    pub observation: Observation,
+    pub reward: f64,
+    pub done: bool,
+}
+
+
+/// The game as an environment for training agents, without a window. The
+/// engine runs one update per low level step, as fast as it is stepped.
+pub struct Env {
+    level: u8,
+    rules: Rules,
+    engine: Engine,
+}
+
+
+impl Env {
+    pub fn new(start_level: u8, rules: Rules) -> Env {
+        Env {
+            level: start_level,
+            engine: Engine::new(start_level, 0, rules.clone()),
+            rules,
+        }
+    }
+
+    /// Starts a new game on `seed`.
+    pub fn reset(&mut self, seed: u64) -> Observation {
+        self.engine = Engine::new(self.level, seed, self.rules.clone());
+        self.observation()
+    }
+
+    pub fn step(&mut self, action: EnvAction) -> Step {
+        let score = self.engine.score();
+        match action {
+            EnvAction::Wait => self.engine.tick(&[]),
+            EnvAction::Key(key) => self.engine.tick(&[key]),
+            EnvAction::Place(placement) => {
+                self.settle();
+                if let States::Falling | States::Locking = *self.engine.state() {
+                    let keys = bot::keys(self.engine.active(), self.engine.grid(), placement);
+                    self.engine.tick(&keys);
+                }
+                self.settle();
+            },
+        }
+        Step {
+            observation: self.observation(),
+            reward: self.engine.score() as f64 - score as f64,
+            done: self.done(),
+        }
+    }
+
+    // Runs through the line clear animation.
+    fn settle(&mut self) {
+        while *self.engine.state() == States::Clearing {
+            self.engine.tick(&[]);
+        }
+    }
+
+    pub fn done(&self) -> bool {
+        matches!(*self.engine.state(), States::GameOver | States::Finished)
+    }
+
+    /// Where `EnvAction::Place` can put the active piece.
+    pub fn placements(&self) -> Vec<Placement> {
+        bot::placements(self.engine.active(), self.engine.grid()).into_iter()
+            .map(|(placement, _)| placement)
+            .collect()
+    }
+
+    pub fn observation(&self) -> Observation {
+        let active = self.engine.active();
+        Observation {
+            cells: self.engine.grid().cells(),
+            active: active.shape(),
+            orientation: active.orientation(),
+            piece: active.blocks().iter().map(|block| (block.x, block.y - 1)).collect(),
+            next: self.engine.peeked().iter().map(|tetrimino| tetrimino.shape()).collect(),
+            hold: self.engine.held().map(|held| held.shape()),
+        }
+    }
+
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use engine::{ Dig, Mode, Rise };
+    use models::RotationSystem;
+
+    fn filled(observation: &Observation) -> usize {
+        observation.cells.iter().flat_map(|row| row.iter()).filter(|&&cell| cell).count()
+    }
+
+    #[test]
+    fn reset_starts_an_empty_well() {
+        let mut env = Env::new(0, Rules::default());
+        let observation = env.reset(9);
+        assert_eq!(observation.cells.len(), 20);
+        assert!(observation.cells.iter().all(|row| row.len() == 10));
+        assert_eq!(filled(&observation), 0);
+        assert_eq!(observation.orientation, 0);
+        assert_eq!(observation.piece.len(), 4);
+        assert!(!observation.next.is_empty());
+        assert_eq!(observation.hold, None);
+        assert_eq!(env.reset(9), observation);
+    }
+
+    #[test]
+    fn keys_run_one_update_each() {
+        let mut env = Env::new(0, Rules::default());
+        let start = env.reset(9);
+        let step = env.step(EnvAction::Key(Action::Left));
+        assert_eq!(env.engine().ticks(), 1);
+        let moved: Vec<(i32, i32)> = start.piece.iter().map(|&(x, y)| (x - 1, y)).collect();
+        assert_eq!(step.observation.piece, moved);
+        assert_eq!((step.reward, step.done), (0.0, false));
+
+        let step = env.step(EnvAction::Wait);
+        assert_eq!(env.engine().ticks(), 2);
+        assert_eq!((step.reward, step.done), (0.0, false));
+
+        let step = env.step(EnvAction::Key(Action::HardDrop));
+        assert_eq!(filled(&step.observation), 4);
+        assert_eq!(step.reward, env.engine().score() as f64);
+        assert!(step.reward > 0.0);
+        assert_eq!(step.observation.active, start.next[0]);
+    }
+
+    #[test]
+    fn place_puts_the_piece_where_asked() {
+        for &rotation in &[RotationSystem::Srs, RotationSystem::Classic] {
+            let mut env = Env::new(0, Rules { rotation, ..Rules::default() });
+            let start = env.reset(2);
+            let placements = env.placements();
+            let mut orientations: Vec<usize> = placements.iter()
+                .map(|placement| placement.orientation)
+                .collect();
+            orientations.sort();
+            orientations.dedup();
+            if start.active != TetriminoType::O {
+                assert!(orientations.len() > 1, "{:?}", rotation);
+            }
+            let placement = *placements.iter()
+                .find(|placement| placement.orientation == *orientations.last().unwrap())
+                .unwrap();
+            let score = env.engine().score();
+            let step = env.step(EnvAction::Place(placement));
+            assert_eq!(step.reward, (env.engine().score() - score) as f64);
+            assert_eq!(filled(&step.observation), 4);
+            let left = step.observation.cells.iter()
+                .filter_map(|row| row.iter().position(|&cell| cell))
+                .min();
+            assert_eq!(left, Some(placement.column as usize));
+            assert_eq!(step.observation.active, start.next[0]);
+            assert!(!step.done);
+        }
+    }
+
+    #[test]
+    fn topping_out_is_done() {
+        let mut env = Env::new(0, Rules::default());
+        env.reset(1);
+        let mut steps = 0;
+        while !env.step(EnvAction::Key(Action::HardDrop)).done {
+            steps += 1;
+            assert!(steps < 1000, "never topped out");
+        }
+        assert!(env.done());
+        assert!(env.step(EnvAction::Wait).done);
+    }
+
+    #[test]
+    fn rising_garbage_ends_the_game() {
+        let rules = Rules {
+            mode: Mode::Dig(Dig { rows: 15, rise: Rise::Pieces(1) }),
+            ..Rules::default()
+        };
+        let mut env = Env::new(0, rules);
+        env.reset(4);
+        let mut steps = 0;
+        // Stacking against the right wall keeps the spawn clear, so it is
+        // the garbage that pushes blocks out of the top.
+        loop {
+            let placement = env.placements().into_iter()
+                .max_by_key(|placement| placement.column)
+                .unwrap();
+            if env.step(EnvAction::Place(placement)).done {
+                break;
+            }
+            steps += 1;
+            assert!(steps < 10000, "never topped out");
+        }
+        assert!(*env.engine().state() == States::GameOver);
+        let cells = env.observation().cells;
+        assert_eq!(cells.len(), 20);
+        assert!(cells.iter().all(|row| row.len() == 10));
+    }
+}
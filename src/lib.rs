@@ -5,479 +5,430 @@ extern crate glutin_window;
 extern crate opengl_graphics;
 extern crate piston;
 extern crate rand;
+extern crate termion;
+
+use std::io;
 
 #[macro_use]
 mod macros;
+mod bot;
+mod engine;
+mod env;
 mod models;
-
-use std::cmp::{max, min};
-use std::mem;
-use std::path::Path;
-
-use graphics::{ Context, Text, Transformed, image, clear, rectangle };
-use graphics::character::CharacterCache;
-use graphics::rectangle::{ Border, Rectangle };
-use glutin_window::GlutinWindow as Window;
-use opengl_graphics::{ GlGraphics, OpenGL, Texture };
-use opengl_graphics::glyph_cache::GlyphCache;
-use piston::event_loop::{ Events, EventLoop, EventSettings };
-use piston::input::{ Button, RenderEvent, PressEvent, Input };
-use piston::input::keyboard::Key;
-use piston::window::WindowSettings;
-
-use models::{ Direction, Grid, Movement, Tetrimino, Tetriminos };
-
-
-const WHITE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
-const GRAY: [f32; 4] = [0.4, 0.4, 0.4, 1.0];
-const BLACKISH: [f32; 4] = [0.05, 0.05, 0.05, 1.0];
-const CLEARISH: [f32; 4] = [0.05, 0.05, 0.05, 0.7];
-
-const CELL_SIZE: f64 = 40.0;
-
-#[derive(Clone, Eq, PartialEq)]
-pub enum States {
-    Falling,
-    Clearing,
-    Locking,
-    Paused(Box<States>),
-    GameOver,
+mod net;
+mod randomizer;
+mod records;
+mod render;
+mod replay;
+mod scoring;
+mod settings;
+mod tui;
+mod versus;
+mod window;
+
+pub use bot::{ Bot, Placement, Weights };
+pub use engine::{ Action, AutoRepeat, Dig, Engine, LockReset, Mode, Rise, Rules, States,
+                  MAX_PREVIEW, TICKS_PER_SECOND };
+pub use env::{ Env, EnvAction, Observation, Step };
+pub use models::{ Block, Direction, Grid, RotationSystem, Tetrimino, TetriminoType };
+pub use net::Online;
+pub use randomizer::{ Bag, Nes, Random, Randomizer, RandomizerKind, Tgm };
+pub use records::Records;
+pub use render::{ Board, Hud, Overlay, Renderer, Snapshot };
+pub use replay::{ Playback, Replay };
+pub use scoring::{ Clear, Spin, Stats };
+pub use settings::Settings;
+pub use versus::{ Outcome, Versus };
+
+
+/// One player, two playing versus at one keyboard, or versus over the
+/// network.
+enum Session {
+    Solo(Box<Engine>),
+    Versus(Versus),
+    Online(Box<Online>),
 }
 
 
-pub struct Game {
-    grid: Grid,
-    tetriminos: Tetriminos,
-    active: Tetrimino,
-    peeked: Tetrimino,
-    state: States,
-    score: u32,
-    default_level: u8,
-    level: u8,
-    fall_ticks: u8,
-    lock_ticks: u8,
-    clear_ticks: u8,
-    lines: u32,
-
-    img: Texture,
-    cache: GlyphCache<'static>,
-}
+impl Session {
+    fn players(&self) -> usize {
+        match *self {
+            Session::Solo(_) => 1,
+            Session::Versus(_) | Session::Online(_) => 2,
+        }
+    }
 
+    fn engine(&self, player: usize) -> &Engine {
+        match *self {
+            Session::Solo(ref engine) => engine,
+            Session::Versus(ref versus) => versus.engine(player),
+            Session::Online(ref online) => online.engine(player),
+        }
+    }
 
-impl Game {
-    fn pause(&mut self) {
-        let curr_state = self.state.clone();
-        self.state = States::Paused(Box::new(curr_state));
+    /// Whether the game or match has ended, so that space starts another.
+    fn over(&self) -> bool {
+        match *self {
+            Session::Solo(ref engine) => {
+                matches!(*engine.state(), States::GameOver | States::Finished)
+            },
+            Session::Versus(ref versus) => versus.outcome().is_some(),
+            Session::Online(ref online) => online.outcome().is_some(),
+        }
     }
 
-    fn resume(&mut self, prev_state: States) {
-        self.state = prev_state;
+    fn tick(&mut self, actions: &[Vec<Action>]) {
+        match *self {
+            Session::Solo(ref mut engine) => engine.tick(&actions[0]),
+            Session::Versus(ref mut versus) => versus.tick(actions),
+            Session::Online(ref mut online) => online.tick(&actions[0]),
+        }
     }
 
-    fn on_move(&mut self, movement: Movement) {
-        match self.state {
-            States::Falling | States::Locking => {
-                let next = self.active.peek(&movement);
-                if !self.grid.is_legal(&next) {
+    fn into_engine(self) -> Engine {
+        match self {
+            Session::Solo(engine) => *engine,
+            _ => panic!("a versus session has two engines"),
+        }
+    }
+}
+
+
+pub struct Game {
+    session: Session,
+    // What each player pressed since the last update.
+    actions: Vec<Vec<Action>>,
+    recording: Option<Replay>,
+    playback: Option<Playback>,
+    bot: Option<Bot>,
+    settings: Settings,
+    // Why the personal bests couldn't be read or saved, if they couldn't.
+    records_error: Option<String>,
+    // The record a finished game was up against, and whether it beat it.
+    best: Option<(Option<u64>, bool)>,
+}
+
+
+impl Game {
+    /// Queues what `player` pressed for the next update. Pausing pauses
+    /// everyone, except online where it would stall the opponent, and hard
+    /// drop starts another game once this one is over.
+    fn press(&mut self, player: usize, action: Action) {
+        let action = match action {
+            Action::Pause => {
+                if let Session::Online(_) = self.session {
                     return;
                 }
-                match movement {
-                    Movement::Rotate => self.active.rotate(&self.grid),
-                    Movement::Shift(direction) => self.active.shift(direction, &self.grid),
-                };
-                let has_landed = self.grid.has_landed(&self.active);
-                if has_landed {
-                    self.state = States::Locking;
-                } else {
-                    if self.state == States::Locking {
-                        self.reset_lock_ticks();
-                    }
-                    self.state = States::Falling;
+                for actions in &mut self.actions {
+                    actions.push(Action::Pause);
                 }
+                return;
             },
-            _ => {},
+            Action::HardDrop if self.session.over() => Action::Restart,
+            action => action,
+        };
+        self.actions[player].push(action);
+    }
+
+    fn on_update(&mut self) {
+        if let Some(ref mut bot) = self.bot {
+            let player = match self.session {
+                Session::Versus(_) => 1,
+                _ => 0,
+            };
+            let actions = bot.actions(self.session.engine(player));
+            self.actions[player].extend(actions);
+        }
+        let tick = self.session.engine(0).ticks();
+        if let Some(ref mut playback) = self.playback {
+            match playback.actions(tick) {
+                Some(actions) => self.actions[0] = actions,
+                None => return,
+            }
+        }
+        if let Some(ref mut recording) = self.recording {
+            for &action in &self.actions[0] {
+                recording.record(tick, action);
+            }
+        }
+        let was_finished = *self.session.engine(0).state() == States::Finished;
+        self.session.tick(&self.actions);
+        for actions in &mut self.actions {
+            actions.clear();
+        }
+        if !was_finished && *self.session.engine(0).state() == States::Finished {
+            self.on_finished();
         }
     }
 
-    fn on_press(&mut self, e: &Input) {
-        if let Some(Button::Keyboard(key)) = e.press_args() {
-            let state = self.state.clone();
-            match state {
-                States::Paused(prev_state) => {
-                    match key {
-                        Key::P => self.resume(prev_state.as_ref().clone()),
-                        _ => {},
-                    }
+    /// Ultra games the player finished count towards their personal best.
+    fn on_finished(&mut self) {
+        self.best = None;
+        self.records_error = None;
+        if self.playback.is_some() || self.bot.is_some() {
+            return;
+        }
+        let engine = self.session.engine(0);
+        if let Mode::Ultra(_) = engine.mode() {
+            let mode = engine.mode().describe();
+            // Records that can't be read are left alone rather than
+            // overwritten with this one game.
+            let mut records = match Records::load(&self.settings.records) {
+                Ok(records) => records,
+                Err(e) => {
+                    self.records_error = Some(format!("cannot read records: {}", e));
+                    return;
                 },
-                States::GameOver => {
-                    match key {
-                        Key::Space => self.restart(),
-                        _ => {},
-                    }
+            };
+            let previous = records.best(&mode);
+            let beaten = records.submit(&mode, engine.score() as u64);
+            if beaten {
+                if let Err(e) = records.save() {
+                    self.records_error = Some(format!("cannot save records: {}", e));
                 }
-                _ => {
-                    match key {
-                        Key::P => self.pause(),
-                        Key::Up => self.on_move(Movement::Rotate),
-                        Key::Down => self.on_move(Movement::Shift(Direction::Down)),
-                        Key::Left => self.on_move(Movement::Shift(Direction::Left)),
-                        Key::Right => self.on_move(Movement::Shift(Direction::Right)),
-                        _ => {},
-                    }
-                },
             }
+            self.best = Some((previous, beaten));
         }
     }
 
-    fn on_update(&mut self) {
-        if !self.grid.is_legal(&self.active.blocks()) {
-            self.state = States::GameOver;
+    /// What the player achieved, for modes with a goal.
+    fn result(&self, player: usize) -> Option<String> {
+        let engine = self.session.engine(player);
+        match (engine.mode(), engine.state()) {
+            (Mode::Sprint(lines), &States::Finished) => {
+                Some(format!("{} lines in {}", lines, format_time(engine.time_ms())))
+            },
+            (Mode::Ultra(_), &States::Finished) => {
+                let score = engine.score();
+                Some(match self.best {
+                    Some((_, true)) => format!("score {}, a new personal best!", score),
+                    Some((Some(best), false)) => format!("score {}, personal best {}", score, best),
+                    _ => format!("score {}", score),
+                })
+            },
+            _ => None,
         }
+    }
 
-        match self.state {
-            States::GameOver => {},
-            States::Paused(_) => {},
-            States::Locking => {
-                let ticks = self.lock_ticks;
-                if ticks > 0 {
-                    self.lock_ticks -= 1;
-                } else {
-                    let mut other = self.tetriminos.next().unwrap();
-                    let peeked = self.tetriminos.peek();
-                    mem::swap(&mut other, &mut self.active);
-                    self.grid.lock(other);
-                    self.peeked = peeked;
-                    self.state = States::Clearing;
-                    self.reset_lock_ticks();
-                    self.reset_fall_ticks();
-                }
+    /// The overlay title once the game or match is over.
+    fn title(&self) -> Option<String> {
+        match self.session {
+            Session::Solo(ref engine) => match *engine.state() {
+                States::GameOver => Some("GAME OVER".to_string()),
+                States::Finished => Some("FINISHED".to_string()),
+                _ => None,
             },
-            States::Clearing => {
-                let ticks = self.clear_ticks;
-                let num_full_rows = self.grid.get_full_rows().len();
-                if num_full_rows == 0 {
-                    self.state = States::Falling;
-                } else if ticks > 0 {
-                    self.clear_ticks -= 1;
-                } else {
-                    let cleared = self.grid.clear_full_rows();
-                    self.update_score(cleared);
-                    self.lines += cleared;
-                    self.state = States::Falling;
-                    self.update_level();
-                    self.reset_clear_ticks();
-                }
+            Session::Versus(ref versus) => match versus.outcome() {
+                Some(Outcome::Winner(player)) => Some(format!("PLAYER {} WINS", player + 1)),
+                Some(Outcome::Draw) => Some("DRAW".to_string()),
+                None => None,
             },
-            States::Falling => {
-                let ticks = self.fall_ticks;
-                if ticks > 0 {
-                    self.fall_ticks -= 1;
-                } else if self.grid.has_landed(&self.active) {
-                    self.state = States::Locking;
-                } else {
-                    self.on_move(Movement::Shift(Direction::Down));
-                    self.reset_fall_ticks();
-                }
+            Session::Online(ref online) => match (online.closed(), online.outcome()) {
+                (Some(_), _) => Some("DISCONNECTED".to_string()),
+                (None, Some(Outcome::Winner(0))) => Some("YOU WIN".to_string()),
+                (None, Some(Outcome::Winner(_))) => Some("YOU LOSE".to_string()),
+                (None, Some(Outcome::Draw)) => Some("DRAW".to_string()),
+                (None, None) => None,
             },
         }
     }
 
-    fn draw_well(&mut self, c: &Context, gl: &mut GlGraphics) {
-        let full_rows = self.grid.get_full_rows();
-        let active_blocks = self.active.blocks();
-        let base_blocks = self.grid.blocks();
-        let blocks = active_blocks.iter()
-            .chain(base_blocks.iter())
-            .filter(|&block| {
-                if self.state == States::Clearing {
-                    if self.clear_ticks % 8 < 4 {
-                        return !full_rows.contains(&block.y);
-                    }
-                }
-                true
-            });
-        let height = self.grid.height;
-        let shade = &self.img;
-
-        rectangle(BLACKISH, [50.0, 0.0, 400.0, 800.0], c.transform, gl);
-
-        for block in blocks {
-            let x_cell = block.x as f64;
-            let y_cell = height as f64 - block.y as f64;
-            let x_pos = 50.0f64 + (x_cell * CELL_SIZE);
-            let y_pos = 0.0f64 + (y_cell * CELL_SIZE);
-            let color = block.color.clone();
-
-            rectangle(color, [x_pos, y_pos, CELL_SIZE, CELL_SIZE], c.transform, gl);
-            image(shade, c.transform.trans(x_pos, y_pos), gl);
+    /// Lines shown under the title once the game or match is over.
+    fn summary(&self) -> Vec<String> {
+        match self.session {
+            Session::Solo(ref engine) => {
+                let stats = engine.stats();
+                let result = self.result(0);
+                let records_error = result.as_ref().and(self.records_error.clone());
+                let mut lines: Vec<String> = result.into_iter().chain(records_error).collect();
+                lines.push(format!("max combo {}   back-to-backs {}   best b2b chain {}   \
+                                    perfect clears {}",
+                                   stats.max_combo, stats.back_to_backs,
+                                   stats.max_back_to_back, stats.perfect_clears));
+                lines
+            },
+            Session::Versus(ref versus) => (0..2).map(|player| {
+                let engine = versus.engine(player);
+                format!("player {}: {} lines, {} garbage sent",
+                        player + 1, engine.lines(), engine.stats().garbage_sent)
+            }).collect(),
+            Session::Online(ref online) => match online.closed() {
+                Some(reason) => vec![reason.to_string()],
+                None => ["you", "opponent"].iter().enumerate().map(|(player, name)| {
+                    let engine = online.engine(player);
+                    format!("{}: {} lines, {} garbage sent",
+                            name, engine.lines(), engine.stats().garbage_sent)
+                }).collect(),
+            },
         }
     }
 
-    fn draw_preview(&mut self, c: &Context, gl: &mut GlGraphics) {
-        let peeked_blocks = self.peeked.blocks();
-        let shade = &self.img;
-
-        let preview_rect = Rectangle::new(BLACKISH).border(
-            Border {
-                color: WHITE,
-                radius: 3.0,
-            });
-        preview_rect.draw([500.0, 550.0, 240.0, 200.0], &c.draw_state,
-                           c.transform, gl);
-
-        for block in &peeked_blocks {
-            let x_cell= (block.x - 2) as f64;
-            let y_cell = 21.0 - block.y as f64;
-            let x_pos = 500.0f64 + (x_cell * CELL_SIZE);
-            let y_pos = 590.0f64 + (y_cell * CELL_SIZE);
-            let color = block.color.clone();
-
-            rectangle(color, [x_pos, y_pos, CELL_SIZE, CELL_SIZE], c.transform, gl);
-            image(shade, c.transform.trans(x_pos, y_pos), gl);
+    /// What the front end draws this frame.
+    pub fn snapshot(&self) -> Snapshot {
+        let overlay = match (self.session.engine(0).state(), self.title()) {
+            (&States::Paused(_), _) => Overlay::Paused,
+            (_, Some(title)) => Overlay::Over(title, self.summary()),
+            (_, None) => Overlay::None,
+        };
+        Snapshot {
+            boards: (0..self.session.players())
+                .map(|player| Board::new(self.session.engine(player), &self.settings))
+                .collect(),
+            overlay,
         }
     }
 
-
-    fn draw_textbox(&mut self, label: &str, val: &str, x: f64, y: f64,
-                    c: &Context, gl: &mut GlGraphics) {
-
-        let ref mut font = self.cache;
-        let val_rect = Rectangle::new(BLACKISH).border(
-            Border {
-                color: WHITE,
-                radius: 3.0,
-            });
-        val_rect.draw([x, y, 200.0, 80.0], &c.draw_state,
-                      c.transform, gl);
-
-        let val_text = Text::new_color(WHITE, 40);
-        let val_width = font.width(40, &val);
-        let val_x_off = (200.0 - val_width) / 2.0;
-        let val_y_off = 60.0;
-        let text_trans = c.transform.trans(x + val_x_off, y + val_y_off);
-        val_text.draw(&val, font, &c.draw_state, text_trans, gl);
-
-        let label_x = x + 50.0;
-        let label_y = y - 15.0;
-        let label_rect = Rectangle::new(BLACKISH).border(
-            Border {
-                color: WHITE,
-                radius: 1.0,
-            });
-        label_rect.draw([label_x, label_y, 100.0, 30.0], &c.draw_state,
-                        c.transform, gl);
-
-        let label_text = Text::new_color(WHITE, 20);
-        let label_width = font.width(20, &label);
-        let label_x_off = (100.0 - label_width) / 2.0;
-        let label_y_off = 25.0;
-        let label_trans = c.transform.trans(label_x + label_x_off,
-                                            label_y + label_y_off);
-        label_text.draw(&label, font, &c.draw_state, label_trans, gl);
+    pub fn run(start_level: u8, seed: u64, rules: Rules, settings: Settings) -> Engine {
+        let engine = Engine::new(start_level, seed, rules.clone());
+        let recording = settings.record.as_ref()
+            .map(|_| Replay::new(seed, start_level, rules));
+        let game = Game::launch(Session::Solo(Box::new(engine)), recording, None, settings);
+        if let (Some(path), Some(mut replay)) = (game.settings.record, game.recording) {
+            replay.finish(game.session.engine(0).ticks());
+            replay.save(&path).expect("cannot write replay");
+        }
+        game.session.into_engine()
     }
 
-
-    fn draw_score(&mut self, c: &Context, gl: &mut GlGraphics) {
-        let score = format!("{:0>6}", self.score);
-        self.draw_textbox("SCORE", &score, 520.0, 50.0, c, gl);
+    pub fn replay(replay: Replay, settings: Settings) -> Engine {
+        let engine = Engine::new(replay.level, replay.seed, replay.rules.clone());
+        let playback = Some(Playback::new(replay));
+        let game = Game::launch(Session::Solo(Box::new(engine)), None, playback, settings);
+        game.session.into_engine()
     }
 
-
-    fn draw_lines(&mut self, c: &Context, gl: &mut GlGraphics) {
-        let lines = format!("{:0>4}", self.lines);
-        self.draw_textbox("LINES", &lines, 520.0, 200.0, c, gl);
+    /// Plays `replay` back as fast as it goes without a front end, drawing
+    /// the start and every update after it with `renderer`.
+    pub fn render_replay(replay: Replay, settings: Settings, renderer: &mut dyn Renderer)
+                         -> io::Result<Engine> {
+        let engine = Engine::new(replay.level, replay.seed, replay.rules.clone());
+        let ticks = replay.ticks;
+        let playback = Some(Playback::new(replay));
+        let mut game = Game::new(Session::Solo(Box::new(engine)), None, playback, settings);
+        renderer.render(&game.snapshot())?;
+        while game.session.engine(0).ticks() < ticks {
+            game.on_update();
+            renderer.render(&game.snapshot())?;
+        }
+        Ok(game.session.into_engine())
     }
 
-    fn draw_level(&mut self, c: &Context, gl: &mut GlGraphics) {
-        let level = format!("{:0>2}", self.level);
-        self.draw_textbox("LEVEL", &level, 520.0, 350.0, c, gl);
+    /// Two players at one keyboard. Versus matches are not recorded.
+    pub fn versus(start_level: u8, seed: u64, rules: Rules, settings: Settings) -> Versus {
+        let versus = Versus::new(start_level, seed, rules);
+        match Game::launch(Session::Versus(versus), None, None, settings).session {
+            Session::Versus(versus) => versus,
+            _ => unreachable!(),
+        }
     }
 
-    fn draw_game_over(&mut self, c: &Context, gl: &mut GlGraphics) {
-        let ref mut font = self.cache;
-        let mut screen_size: [f64; 4] = [0.0, 0.0, 0.0, 0.0];
-        let rect = c.viewport.unwrap().rect;
-        for (idx, _) in rect.iter().enumerate() {
-            screen_size[idx] = rect[idx] as f64;
+    /// Versus against `online`'s opponent, saying goodbye when the window
+    /// is closed.
+    pub fn online(online: Online, settings: Settings) -> Online {
+        let session = Session::Online(Box::new(online));
+        match Game::launch(session, None, None, settings).session {
+            Session::Online(mut online) => {
+                online.close();
+                *online
+            },
+            _ => unreachable!(),
         }
-        let center_x = (screen_size[2]) / 2.0;
-        let center_y = (screen_size[3]) / 2.0;
-        let overlay = Rectangle::new(CLEARISH);
-        overlay.draw(screen_size, &c.draw_state, c.transform, gl);
-
-        let game_over = "GAME OVER";
-        let game_over_text = Text::new_color([1.0, 1.0, 1.0, 0.8], 100);
-        let game_over_width = font.width(100, &game_over);
-        let game_over_x_pos = center_x - (game_over_width / 2.0);
-        let game_over_y_pos = center_y;
-        let game_over_trans = c.transform
-            .trans(game_over_x_pos, game_over_y_pos);
-        game_over_text.draw(&game_over, font, &c.draw_state, game_over_trans, gl);
-
-        let cont = "[spacebar to continue]";
-        let cont_text = Text::new_color([1.0, 1.0, 1.0, 0.8], 20);
-        let cont_width = font.width(20, &cont);
-        let cont_x_pos = center_x - (cont_width / 2.0);
-        let cont_y_pos = game_over_y_pos + 50.0;
-        let cont_trans = c.transform
-            .trans(cont_x_pos, cont_y_pos);
-        cont_text.draw(&cont, font, &c.draw_state, cont_trans, gl);
     }
 
-
-    fn draw_paused(&mut self, c: &Context, gl: &mut GlGraphics) {
-        let ref mut font = self.cache;
-        let mut screen_size: [f64; 4] = [0.0, 0.0, 0.0, 0.0];
-        let rect = c.viewport.unwrap().rect;
-        for (idx, _) in rect.iter().enumerate() {
-            screen_size[idx] = rect[idx] as f64;
+    fn launch(session: Session, recording: Option<Replay>, playback: Option<Playback>,
+              settings: Settings) -> Game {
+        let mut game = Game::new(session, recording, playback, settings);
+        if game.settings.tui {
+            tui::run(&mut game).expect("cannot draw in the terminal");
+        } else {
+            window::run(&mut game);
         }
-        let center_x = (screen_size[2]) / 2.0;
-        let center_y = (screen_size[3]) / 2.0;
-        let overlay = Rectangle::new(CLEARISH);
-        overlay.draw(screen_size, &c.draw_state, c.transform, gl);
-        let letters = "PAUSED";
-        let text = Text::new_color([1.0, 1.0, 1.0, 0.8], 100);
-        let text_width = font.width(100, &letters);
-        let x_pos = center_x - (text_width / 2.0);
-        let y_pos = center_y + (100.0 / 2.0);
-        let text_trans = c.transform
-            .trans(x_pos, y_pos)
-            .rot_deg(-22.0);
-        text.draw(&letters, font, &c.draw_state, text_trans, gl);
+        game
     }
 
+    fn new(session: Session, recording: Option<Replay>, playback: Option<Playback>,
+           settings: Settings) -> Game {
+        Game {
+            actions: vec![vec![]; session.players()],
+            session,
+            bot: if settings.bot { Some(Bot::new(Weights::default())) } else { None },
+            recording,
+            playback,
+            records_error: None,
+            best: None,
+            settings,
+        }
+    }
+}
 
-    fn on_render(&mut self, e: &Input, gl: &mut GlGraphics) {
-        let args = e.render_args().unwrap();
 
-        gl.draw(args.viewport(), |c, gl| {
-            clear(GRAY, gl);
+/// Formats a time in milliseconds as `m:ss.mmm`.
+pub fn format_time(ms: u64) -> String {
+    format!("{}:{:02}.{:03}", ms / 60_000, ms / 1000 % 60, ms % 1000)
+}
 
-            self.draw_well(&c, gl);
-            self.draw_preview(&c, gl);
-            self.draw_score(&c, gl);
-            self.draw_lines(&c, gl);
-            self.draw_level(&c, gl);
 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            match self.state {
-                States::Paused(_) => self.draw_paused(&c, gl),
-                States::GameOver => self.draw_game_over(&c, gl),
-                _ => {},
-            }
-        });
+    // Keeps every snapshot it is asked to draw.
+    struct Recorder {
+        snapshots: Vec<Snapshot>,
     }
 
-    fn update_level(&mut self) {
-        let lines = self.lines;
-        self.level = max(self.level, min(lines / 10, 20) as u8);
-    }
 
-    fn update_score(&mut self, num_rows_cleared: u32) {
-        let l = (self.level + 1) as u32;
-        match num_rows_cleared {
-            1 => { self.score += 40u32 * l; },
-            2 => { self.score += 100u32 * l; },
-            3 => { self.score += 300u32 * l; },
-            4 => { self.score += 1200u32 * l; },
-            _ => {},
+    impl Renderer for Recorder {
+        fn render(&mut self, snapshot: &Snapshot) -> io::Result<()> {
+            self.snapshots.push(snapshot.clone());
+            Ok(())
         }
     }
 
-    fn restart(&mut self) {
-        let active = self.tetriminos.next().unwrap();
-        let peeked = self.tetriminos.peek();
-        self.active = active;
-        self.peeked = peeked;
-        self.grid = Grid::new(20, 10);
-        self.level = self.default_level;
-        self.fall_ticks = 53;
-        self.lock_ticks = 10;
-        self.clear_ticks = 48;
-        self.score = 0;
-        self.lines = 0;
-        self.state = States::Falling;
+    fn locked(board: &Board) -> usize {
+        board.cells.iter().flat_map(|row| row.iter()).filter(|cell| cell.is_some()).count()
     }
 
-    fn reset_fall_ticks(&mut self) {
-        self.fall_ticks = match self.level {
-            0 => 53,
-            1 => 49,
-            2 => 45,
-            3 => 41,
-            4 => 37,
-            5 => 33,
-            6 => 28,
-            7 => 22,
-            8 => 17,
-            9 => 11,
-            10 => 10,
-            11 => 9,
-            12 => 8,
-            13 => 7,
-            14 => 6,
-            15 => 6,
-            16 => 5,
-            17 => 5,
-            18 => 4,
-            19 => 4,
-            20 => 3,
-            _ => panic!("illegal level"),
-        }
-    }
-
-    fn reset_lock_ticks(&mut self) {
-        self.lock_ticks = 10;
-    }
-
-    fn reset_clear_ticks(&mut self) {
-        self.clear_ticks = 93;
-    }
-
-    pub fn run(start_level: u8) {
-        let opengl = OpenGL::V3_2;
-        let font_path = Path::new("assets/Verdana.ttf");
-        let mut window: Window = WindowSettings::new(
-            "tetris",
-            [800, 800])
-            .opengl(opengl)
-            .exit_on_esc(true)
-            .build()
-            .unwrap();
-        let mut tetriminos = Tetriminos::init();
-        let active = tetriminos.next().unwrap();
-        let peeked = tetriminos.peek();
-        let mut game = Game {
-            grid: Grid::new(20, 10),
-            tetriminos,
-            active,
-            peeked,
-            default_level: start_level,
-            level: start_level,
-            fall_ticks: 53,
-            lock_ticks: 10,
-            clear_ticks: 48,
-            score: 0,
-            lines: 0,
-            state: States::Falling,
-
-            img: Texture::from_path("assets/shade.png").unwrap(),
-            cache: GlyphCache::new(font_path).unwrap(),
+    #[test]
+    fn renderers_see_every_update() {
+        let mut replay = Replay::new(3, 0, Rules::default());
+        replay.record(0, Action::HardDrop);
+        replay.record(5, Action::Hold);
+        replay.record(90, Action::Pause);
+        replay.finish(100);
+        let settings = Settings {
+            preview: 3,
+            ..Settings::default()
         };
-        let ref mut gl = GlGraphics::new(opengl);
-
-        let mut settings = EventSettings::new();
-        settings.set_ups(60);
-        settings.set_max_fps(60);
-        let mut events = Events::new(settings);
-        while let Some(e) = events.next(&mut window) {
-            match e {
-                Input::Render(_) => game.on_render(&e, gl),
-                Input::Press(_) => game.on_press(&e),
-                Input::Update(_) => game.on_update(),
-                _ => {},
-            }
-        }
+        let mut recorder = Recorder { snapshots: vec![] };
+        let engine = Game::render_replay(replay, settings, &mut recorder).unwrap();
+        let snapshots = recorder.snapshots;
+        assert_eq!(snapshots.len(), 101);
+
+        let start = &snapshots[0];
+        assert_eq!(start.overlay, Overlay::None);
+        assert_eq!(start.boards.len(), 1);
+        let board = &start.boards[0];
+        assert_eq!((board.width(), board.height()), (10, 20));
+        assert_eq!(locked(board), 0);
+        assert_eq!(board.active.len(), 4);
+        assert_eq!(board.ghost.len(), 4);
+        assert_eq!(board.queue.len(), 3);
+        assert!(board.hold.is_empty() && board.can_hold);
+        assert_eq!(board.hud, Hud::default());
+
+        let dropped = &snapshots[1].boards[0];
+        assert_eq!(locked(dropped), 4);
+        assert!(dropped.hud.score > 0);
+        assert_eq!(dropped.active, start.boards[0].queue[0]);
+
+        let held = &snapshots[6].boards[0];
+        assert_eq!(held.hold.len(), 4);
+        assert!(!held.can_hold);
+
+        let paused = &snapshots[100];
+        assert_eq!(paused.overlay, Overlay::Paused);
+        assert_eq!(paused.boards[0].hud.score, engine.score());
+        assert_eq!(locked(&paused.boards[0]), engine.grid().blocks().len());
     }
 }
@@ -1,13 +1,13 @@
+// The piece shapes are built into the binary, so the engine doesn't care
+// which directory it is run from.
 #[macro_export]
 macro_rules! states {
-    ($shape:expr) => {
+    ($shape:tt) => {
+        states!("states", $shape)
+    };
+    ($dir:tt, $shape:tt) => {
         {
-            use std::fs::File;
-            use std::io::prelude::*;
-            let filename = format!("assets/states/{}.states", $shape);
-            let mut f = File::open(filename).expect("missing states file");
-            let mut contents = String::new();
-            f.read_to_string(&mut contents).expect("cannot read states file");
+            let contents = include_str!(concat!("../assets/", $dir, "/", $shape, ".states"));
             let shapes = contents.trim().split("====\n").map(|s| s.to_string());
             let mut states: Vec<Vec<Vec<bool>>> = vec![];
             for (shape_num, shape) in shapes.enumerate() {
@@ -28,3 +28,20 @@ macro_rules! states {
         }
     }
 }
+
+
+// Every shape's states from `assets/<dir>`.
+#[macro_export]
+macro_rules! all_states {
+    ($dir:tt) => {
+        [
+            (TetriminoType::O, states!($dir, "O")),
+            (TetriminoType::I, states!($dir, "I")),
+            (TetriminoType::T, states!($dir, "T")),
+            (TetriminoType::S, states!($dir, "S")),
+            (TetriminoType::Z, states!($dir, "Z")),
+            (TetriminoType::J, states!($dir, "J")),
+            (TetriminoType::L, states!($dir, "L")),
+        ].iter().cloned().collect()
+    }
+}
@@ -1,10 +1,15 @@
 extern crate clap;
+extern crate rand;
 extern crate tetris;
 
 
-use clap::{Arg, App};
+use std::path::{ Path, PathBuf };
 
-use tetris::Game;
+use clap::{Arg, App, SubCommand};
+
+use tetris::{ AutoRepeat, Dig, Engine, Game, LockReset, Mode, Online, Outcome, RandomizerKind,
+              Replay, Rise, RotationSystem, Rules, Settings, MAX_PREVIEW, TICKS_PER_SECOND,
+              format_time };
 
 
 fn main() {
@@ -17,8 +22,145 @@ fn main() {
              .long("level")
              .takes_value(true)
              .help("Starting level (0-20)"))
+        .arg(Arg::with_name("seed")
+             .short("s")
+             .long("seed")
+             .takes_value(true)
+             .help("Seed for the piece sequence"))
+        .arg(Arg::with_name("record")
+             .short("r")
+             .long("record")
+             .takes_value(true)
+             .help("Save a replay of the session to this file"))
+        .arg(Arg::with_name("mode")
+             .short("m")
+             .long("mode")
+             .takes_value(true)
+             .possible_values(&["marathon", "sprint", "ultra", "dig", "versus"])
+             .help("Game mode (default marathon), versus is two players at one keyboard"))
+        .arg(Arg::with_name("host")
+             .long("host")
+             .takes_value(true)
+             .value_name("port")
+             .conflicts_with("connect")
+             .help("Wait on this port for a player to join a versus match"))
+        .arg(Arg::with_name("connect")
+             .long("connect")
+             .takes_value(true)
+             .value_name("addr")
+             .help("Join the versus match hosted at this address, like 192.168.1.20:4000"))
+        .arg(Arg::with_name("lines")
+             .long("lines")
+             .takes_value(true)
+             .help("Lines to clear in sprint (default 40)"))
+        .arg(Arg::with_name("time")
+             .long("time")
+             .takes_value(true)
+             .help("Seconds to play in ultra (default 120)"))
+        .arg(Arg::with_name("rows")
+             .long("rows")
+             .takes_value(true)
+             .help("Garbage rows to start dig with (1-18, default 10)"))
+        .arg(Arg::with_name("rise-pieces")
+             .long("rise-pieces")
+             .takes_value(true)
+             .conflicts_with("rise-seconds")
+             .help("Add a garbage row in dig every this many pieces"))
+        .arg(Arg::with_name("rise-seconds")
+             .long("rise-seconds")
+             .takes_value(true)
+             .help("Add a garbage row in dig every this many seconds"))
+        .arg(Arg::with_name("rotation")
+             .long("rotation")
+             .takes_value(true)
+             .possible_values(&["srs", "classic"])
+             .help("Rotation system (default srs)"))
+        .arg(Arg::with_name("randomizer")
+             .long("randomizer")
+             .takes_value(true)
+             .possible_values(&["bag7", "bag14", "random", "nes", "tgm"])
+             .help("How upcoming pieces are picked (default bag7)"))
+        .arg(Arg::with_name("next")
+             .short("n")
+             .long("next")
+             .takes_value(true)
+             .help("Number of upcoming pieces to show (1-6)"))
+        .arg(Arg::with_name("das")
+             .long("das")
+             .takes_value(true)
+             .help("Ticks Left/Right are held before they repeat (default 10)"))
+        .arg(Arg::with_name("arr")
+             .long("arr")
+             .takes_value(true)
+             .help("Ticks between Left/Right repeats, 0 for instant (default 2)"))
+        .arg(Arg::with_name("soft-drop-das")
+             .long("soft-drop-das")
+             .takes_value(true)
+             .help("Ticks Down is held before soft drop speeds up (default 0)"))
+        .arg(Arg::with_name("soft-drop-arr")
+             .long("soft-drop-arr")
+             .takes_value(true)
+             .help("Ticks between soft drop steps (default 2)"))
+        .arg(Arg::with_name("lock-delay")
+             .long("lock-delay")
+             .takes_value(true)
+             .help("Ticks a grounded piece waits before locking (default 30)"))
+        .arg(Arg::with_name("lock-reset")
+             .long("lock-reset")
+             .takes_value(true)
+             .possible_values(&["classic", "step", "move"])
+             .help("What resets the lock delay (default move, capped at 15 resets)"))
+        .arg(Arg::with_name("bot")
+             .long("bot")
+             .help("Let the computer play, or be the second player in versus"))
+        .arg(Arg::with_name("tui")
+             .long("tui")
+             .help("Play in the terminal instead of a window, such as over SSH"))
+        .arg(Arg::with_name("no-ghost")
+             .long("no-ghost")
+             .help("Hide the ghost piece (toggle in game with G)"))
+        .subcommand(SubCommand::with_name("replay")
+                    .about("Plays back a recorded game")
+                    .arg(Arg::with_name("file")
+                         .required(true)
+                         .help("Replay file to play"))
+                    .arg(Arg::with_name("headless")
+                         .long("headless")
+                         .help("Play back without a window and print the result")))
         .get_matches();
 
+    let mut settings = Settings {
+        ghost: !matches.is_present("no-ghost"),
+        bot: matches.is_present("bot"),
+        tui: matches.is_present("tui"),
+        ..Settings::default()
+    };
+    if let Some(s) = matches.value_of("next") {
+        settings.preview = match s.parse::<usize>() {
+            Ok(n) if (1..=MAX_PREVIEW).contains(&n) => n,
+            _ => panic!("Invalid preview count :((("),
+        };
+    }
+
+    if let Some(matches) = matches.subcommand_matches("replay") {
+        let path = Path::new(matches.value_of("file").unwrap());
+        let replay = Replay::load(path).expect("Invalid replay file :(((");
+        let engine = if matches.is_present("headless") {
+            replay.play()
+        } else {
+            Game::replay(replay, settings)
+        };
+        report(&engine);
+        return;
+    }
+
+    if let Some(addr) = matches.value_of("connect") {
+        let online = Online::connect(addr).expect("Cannot join the match :(((");
+        println!("seed: {}", online.seed());
+        play_online(online, settings);
+        return;
+    }
+
     let level_str = matches.value_of("level");
     let level: u8 = match level_str {
         None => 0u8,
@@ -29,5 +171,119 @@ fn main() {
             }
         },
     };
-    Game::run(level);
+    let seed: u64 = match matches.value_of("seed") {
+        None => rand::random(),
+        Some(s) => s.parse::<u64>().expect("Invalid seed :((("),
+    };
+    println!("seed: {}", seed);
+    let mut rules = Rules::default();
+    let lines = match matches.value_of("lines") {
+        None => 40,
+        Some(s) => match s.parse::<u32>() {
+            Ok(n) if n > 0 => n,
+            _ => panic!("Invalid line target :((("),
+        },
+    };
+    let seconds = match matches.value_of("time") {
+        None => 120,
+        Some(s) => match s.parse::<u64>() {
+            Ok(n) if n > 0 => n,
+            _ => panic!("Invalid time limit :((("),
+        },
+    };
+    let rows = match matches.value_of("rows") {
+        None => 10,
+        Some(s) => match s.parse::<u32>() {
+            Ok(n) if (1..=18).contains(&n) => n,
+            _ => panic!("Invalid garbage row count :((("),
+        },
+    };
+    let every = |name| matches.value_of(name).map(|s| match s.parse::<u32>() {
+        Ok(n) if n > 0 => n,
+        _ => panic!("Invalid garbage rate :((("),
+    });
+    let rise = match (every("rise-pieces"), every("rise-seconds")) {
+        (Some(pieces), _) => Rise::Pieces(pieces),
+        (_, Some(seconds)) => Rise::Ticks(seconds as u64 * TICKS_PER_SECOND),
+        _ => Rise::Never,
+    };
+    rules.mode = match matches.value_of("mode") {
+        Some("sprint") => Mode::Sprint(lines),
+        Some("ultra") => Mode::Ultra(seconds * TICKS_PER_SECOND),
+        Some("dig") => Mode::Dig(Dig { rows, rise }),
+        Some("versus") => Mode::Versus,
+        _ => Mode::Marathon,
+    };
+    if let Some(name) = matches.value_of("rotation") {
+        rules.rotation = RotationSystem::from_name(name).unwrap();
+    }
+    if let Some(name) = matches.value_of("randomizer") {
+        rules.randomizer = RandomizerKind::from_name(name).unwrap();
+    }
+    if let Some(shift) = rules.shift {
+        rules.shift = Some(AutoRepeat {
+            delay: parse_ticks(matches.value_of("das"), shift.delay),
+            rate: parse_ticks(matches.value_of("arr"), shift.rate),
+        });
+    }
+    rules.soft_drop = AutoRepeat {
+        delay: parse_ticks(matches.value_of("soft-drop-das"), rules.soft_drop.delay),
+        rate: parse_ticks(matches.value_of("soft-drop-arr"), rules.soft_drop.rate),
+    };
+    rules.lock_delay = parse_ticks(matches.value_of("lock-delay"), rules.lock_delay);
+    if let Some(name) = matches.value_of("lock-reset") {
+        rules.lock_reset = LockReset::from_name(name).unwrap();
+    }
+    if let Some(port) = matches.value_of("host") {
+        let port = port.parse::<u16>().expect("Invalid port :(((");
+        println!("waiting for a player on port {}", port);
+        let online = Online::host(port, level, seed, rules).expect("Cannot host the match :(((");
+        play_online(online, settings);
+        return;
+    }
+    if rules.mode == Mode::Versus {
+        let versus = Game::versus(level, seed, rules, settings);
+        match versus.outcome() {
+            Some(Outcome::Winner(player)) => println!("winner: player {}", player + 1),
+            Some(Outcome::Draw) => println!("draw"),
+            None => {},
+        }
+        for player in 0..2 {
+            println!("player {}:", player + 1);
+            report(versus.engine(player));
+        }
+        return;
+    }
+    settings.record = matches.value_of("record").map(PathBuf::from);
+    let engine = Game::run(level, seed, rules, settings);
+    report(&engine);
+}
+
+
+fn play_online(online: Online, settings: Settings) {
+    let online = Game::online(online, settings);
+    match online.outcome() {
+        Some(Outcome::Winner(0)) => println!("you won"),
+        Some(Outcome::Winner(_)) => println!("you lost"),
+        Some(Outcome::Draw) => println!("draw"),
+        None => {},
+    }
+    report(online.engine(0));
+}
+
+
+fn report(engine: &Engine) {
+    println!("ticks: {}", engine.ticks());
+    println!("score: {}", engine.score());
+    println!("lines: {}", engine.lines());
+    println!("level: {}", engine.level());
+    println!("time: {}", format_time(engine.time_ms()));
+}
+
+
+fn parse_ticks(value: Option<&str>, default: u8) -> u8 {
+    match value {
+        None => default,
+        Some(s) => s.parse::<u8>().expect("Invalid tick count :((("),
+    }
 }
@@ -2,16 +2,22 @@ use std::collections::{ HashMap, VecDeque };
 use std::iter::{ Iterator };
 
 use graphics::color::hex;
-use rand::{ thread_rng, Rng };
+
+use randomizer::{ Randomizer, RandomizerKind };
+
+
+const GARBAGE_COLOR: [f32; 4] = [0.5, 0.5, 0.5, 1.0];
 
 
 pub enum Movement {
     Rotate,
+    RotateCcw,
+    Rotate180,
     Shift(Direction),
 }
 
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Direction {
     Left,
     Right,
@@ -19,7 +25,7 @@ pub enum Direction {
 }
 
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, PartialOrd, Ord)]
 pub enum TetriminoType {
     O,
     I,
@@ -47,6 +53,107 @@ impl TetriminoType {
 }
 
 
+// SRS wall kicks as (x, y) offsets, tried in order. Rows are the
+// transitions 0->R, R->0, R->2, 2->R, 2->L, L->2, L->0 and 0->L.
+const JLSTZ_KICKS: [[(i32, i32); 5]; 8] = [
+    [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+    [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+    [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+    [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+    [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+    [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+    [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+    [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+];
+
+const I_KICKS: [[(i32, i32); 5]; 8] = [
+    [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
+    [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
+    [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
+    [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
+    [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
+    [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
+    [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
+    [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
+];
+
+// SRS has no 180 degree kicks, these are the ones most modern games use.
+// Rows are the transitions 0->2, 2->0, R->L and L->R.
+const FLIP_KICKS: [[(i32, i32); 6]; 4] = [
+    [(0, 0), (0, 1), (1, 1), (-1, 1), (1, 0), (-1, 0)],
+    [(0, 0), (0, -1), (-1, -1), (1, -1), (-1, 0), (1, 0)],
+    [(0, 0), (1, 0), (1, 2), (1, 1), (0, 2), (0, 1)],
+    [(0, 0), (-1, 0), (-1, 2), (-1, 1), (0, 2), (0, 1)],
+];
+
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RotationSystem {
+    Classic,
+    Srs,
+}
+
+
+impl RotationSystem {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            RotationSystem::Classic => "classic",
+            RotationSystem::Srs => "srs",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<RotationSystem> {
+        match name {
+            "classic" => Some(RotationSystem::Classic),
+            "srs" => Some(RotationSystem::Srs),
+            _ => None,
+        }
+    }
+
+    fn spawn_y(&self, shape: TetriminoType) -> i32 {
+        match (*self, shape) {
+            (RotationSystem::Srs, TetriminoType::I) => 21,
+            (RotationSystem::Srs, _) => 20,
+            (RotationSystem::Classic, _) => 21,
+        }
+    }
+
+    fn kicks(&self, shape: TetriminoType, from: usize, to: usize) -> Vec<(i32, i32)> {
+        let flip = match (from, to) {
+            (0, 2) => Some(0),
+            (2, 0) => Some(1),
+            (1, 3) => Some(2),
+            (3, 1) => Some(3),
+            _ => None,
+        };
+        if let Some(flip) = flip {
+            return match (*self, shape) {
+                (RotationSystem::Srs, TetriminoType::O) => vec![(0, 0)],
+                (RotationSystem::Srs, _) => FLIP_KICKS[flip].to_vec(),
+                (RotationSystem::Classic, _) => vec![(0, 0)],
+            };
+        }
+        let transition = match (from, to) {
+            (0, 1) => 0,
+            (1, 0) => 1,
+            (1, 2) => 2,
+            (2, 1) => 3,
+            (2, 3) => 4,
+            (3, 2) => 5,
+            (3, 0) => 6,
+            (0, 3) => 7,
+            _ => return vec![(0, 0)],
+        };
+        match (*self, shape) {
+            (RotationSystem::Classic, _) => vec![(0, 0)],
+            (RotationSystem::Srs, TetriminoType::O) => vec![(0, 0)],
+            (RotationSystem::Srs, TetriminoType::I) => I_KICKS[transition].to_vec(),
+            (RotationSystem::Srs, _) => JLSTZ_KICKS[transition].to_vec(),
+        }
+    }
+}
+
+
 #[derive(Clone, Debug)]
 struct Rotation {
     internal: Vec<Vec<Vec<bool>>>,
@@ -62,12 +169,9 @@ impl Rotation {
         }
     }
 
-    fn next_idx(&self) -> usize {
-        let mut idx = self.curr_idx + 1;
-        if idx >= self.internal.len() {
-            idx = 0;
-        }
-        idx
+    // `turns` counts clockwise quarter turns from the current state.
+    fn next_idx(&self, turns: usize) -> usize {
+        (self.curr_idx + turns) % self.internal.len()
     }
 
     fn as_blocks(state: &Vec<Vec<bool>>, x_offset: i32, y_offset: i32, color: [f32; 4])
@@ -94,14 +198,19 @@ impl Rotation {
     }
 
 
-    fn peek_as_blocks(&self, x_offset: i32, y_offset: i32, color: [f32; 4]) -> Vec<Block> {
-        let next_idx = self.next_idx();
+    fn peek_as_blocks(&self, turns: usize, x_offset: i32, y_offset: i32, color: [f32; 4])
+                      -> Vec<Block> {
+        let next_idx = self.next_idx(turns);
         Rotation::as_blocks(self.internal.get(next_idx).unwrap(),
                          x_offset, y_offset, color)
     }
 
-    fn change(&mut self) {
-        self.curr_idx = self.next_idx();
+    fn change(&mut self, turns: usize) {
+        self.curr_idx = self.next_idx(turns);
+    }
+
+    fn reset(&mut self) {
+        self.curr_idx = 0;
     }
 }
 
@@ -112,16 +221,11 @@ struct States {
 
 
 impl States {
-    fn init() -> States {
-        let tet_states: HashMap<TetriminoType, Vec<Vec<Vec<bool>>>> = [
-            (TetriminoType::O, states!("O")),
-            (TetriminoType::I, states!("I")),
-            (TetriminoType::T, states!("T")),
-            (TetriminoType::S, states!("S")),
-            (TetriminoType::Z, states!("Z")),
-            (TetriminoType::J, states!("J")),
-            (TetriminoType::L, states!("L")),
-        ].iter().cloned().collect();
+    fn init(system: RotationSystem) -> States {
+        let tet_states: HashMap<TetriminoType, Vec<Vec<Vec<bool>>>> = match system {
+            RotationSystem::Classic => all_states!("states"),
+            RotationSystem::Srs => all_states!("srs"),
+        };
         States {
             states: tet_states,
         }
@@ -130,42 +234,41 @@ impl States {
 
 pub struct Tetriminos {
     states: States,
+    system: RotationSystem,
     queued: VecDeque<Tetrimino>,
+    randomizer: Box<dyn Randomizer>,
 }
 
 
 impl Tetriminos {
-    pub fn init() -> Tetriminos {
+    pub fn new(seed: u64, system: RotationSystem, randomizer: RandomizerKind)
+               -> Tetriminos {
         Tetriminos {
-            states: States::init(),
+            states: States::init(system),
+            system,
             queued: VecDeque::new(),
+            randomizer: randomizer.create(seed),
         }
     }
     pub fn states(&self) -> &HashMap<TetriminoType, Vec<Vec<Vec<bool>>>> {
         &self.states.states
     }
-    pub fn types(&self) -> Vec<TetriminoType> {
-        self.states.states.keys().map(|k| k.clone()).collect()
-    }
-
-    fn maybe_refill_queue(&mut self) -> bool {
-        let mut was_empty = false;
-        if self.queued.is_empty() {
-            let mut rng = thread_rng();
-            let mut types = self.types();
-            rng.shuffle(&mut types);
-            let next_gen: VecDeque<Tetrimino> = types.into_iter()
-                .map(|tet_type| Tetrimino::new(tet_type.clone(), &self))
-                .collect();
-            self.queued.extend(next_gen);
-            was_empty = true;
+
+    fn maybe_refill_queue(&mut self, min_len: usize) -> bool {
+        let mut was_short = false;
+        while self.queued.len() < min_len {
+            let tet_type = self.randomizer.next_type();
+            let next = Tetrimino::new(tet_type, &self);
+            self.queued.push_back(next);
+            was_short = true;
         }
-        was_empty
+        was_short
     }
 
-    pub fn peek(&mut self) -> Tetrimino {
-        self.maybe_refill_queue();
-        self.queued[0].clone()
+    /// The next `n` pieces, generating as many as that takes.
+    pub fn peek_n(&mut self, n: usize) -> Vec<Tetrimino> {
+        self.maybe_refill_queue(n);
+        self.queued.iter().take(n).cloned().collect()
     }
 }
 
@@ -174,7 +277,7 @@ impl Iterator for Tetriminos {
     type Item = Tetrimino;
 
     fn next(&mut self) -> Option<Tetrimino> {
-        self.maybe_refill_queue();
+        self.maybe_refill_queue(1);
         self.queued.pop_front()
     }
 }
@@ -184,6 +287,8 @@ impl Iterator for Tetriminos {
 pub struct Tetrimino {
     shape: TetriminoType,
     rotation: Rotation,
+    system: RotationSystem,
+    kick: usize,
     x: i32,
     y: i32,
     color: [f32; 4],
@@ -194,16 +299,40 @@ impl Tetrimino {
     pub fn new(shape: TetriminoType, tetriminos: &Tetriminos)
                -> Tetrimino {
         let rotation = Rotation::new(tetriminos.states().get(&shape).unwrap().clone());
+        let system = tetriminos.system;
         let color = shape.color();
         Tetrimino {
             shape,
             rotation,
+            system,
+            kick: 0,
             color,
             x: 3,
-            y: 21,
+            y: system.spawn_y(shape),
         }
     }
 
+    pub fn shape(&self) -> TetriminoType {
+        self.shape
+    }
+
+    /// Clockwise quarter turns from the state the piece spawned in.
+    pub fn orientation(&self) -> usize {
+        self.rotation.curr_idx
+    }
+
+    /// Index into the kick table of the offset used by the last rotation.
+    pub fn last_kick(&self) -> usize {
+        self.kick
+    }
+
+    /// Moves the piece back to where it spawned, in its first rotation state.
+    pub fn reset(&mut self) {
+        self.rotation.reset();
+        self.x = 3;
+        self.y = self.system.spawn_y(self.shape);
+    }
+
     pub fn shift(&mut self, direction: Direction, on_grid: &Grid) -> bool {
         let mut min_y = on_grid.height;
         let mut min_x = on_grid.width;
@@ -239,16 +368,43 @@ impl Tetrimino {
     }
 
     pub fn rotate(&mut self, on_grid: &Grid) -> bool {
-        let next = self.rotation.peek_as_blocks(self.x, self.y, self.color.clone());
-        match on_grid.is_legal(&next) {
-            true => { self.rotation.change(); true },
-            false => { false },
+        self.turn(1, on_grid)
+    }
+
+    pub fn rotate_ccw(&mut self, on_grid: &Grid) -> bool {
+        self.turn(3, on_grid)
+    }
+
+    pub fn rotate_180(&mut self, on_grid: &Grid) -> bool {
+        self.turn(2, on_grid)
+    }
+
+    // Tries each of the rotation system's kick offsets in order until one
+    // lands somewhere legal.
+    fn turn(&mut self, turns: usize, on_grid: &Grid) -> bool {
+        let from = self.rotation.curr_idx;
+        let to = self.rotation.next_idx(turns);
+        let kicks = self.system.kicks(self.shape, from, to);
+        for (kick, &(x_kick, y_kick)) in kicks.iter().enumerate() {
+            let x = self.x + x_kick;
+            let y = self.y + y_kick;
+            let next = self.rotation.peek_as_blocks(turns, x, y, self.color);
+            if on_grid.is_legal(&next) {
+                self.rotation.change(turns);
+                self.kick = kick;
+                self.x = x;
+                self.y = y;
+                return true;
+            }
         }
+        false
     }
 
     pub fn peek(&self, movement: &Movement) -> Vec<Block> {
         match movement {
-            &Movement::Rotate => self.rotation.peek_as_blocks(self.x, self.y, self.color.clone()),
+            &Movement::Rotate => self.rotation.peek_as_blocks(1, self.x, self.y, self.color),
+            &Movement::RotateCcw => self.rotation.peek_as_blocks(3, self.x, self.y, self.color),
+            &Movement::Rotate180 => self.rotation.peek_as_blocks(2, self.x, self.y, self.color),
             &Movement::Shift(ref dir) => {
                 let blocks = self.blocks();
                 match dir {
@@ -296,6 +452,7 @@ impl PartialEq for Block {
 }
 
 
+#[derive(Clone, Debug)]
 pub struct Grid {
     pub height: i32,
     pub width: i32,
@@ -319,6 +476,24 @@ impl Grid {
         result
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Which cells hold a block, as rows of columns with the bottom row
+    /// first.
+    pub fn cells(&self) -> Vec<Vec<bool>> {
+        let mut cells = vec![vec![false; self.width as usize]; self.height as usize];
+        for block in &self.blocks {
+            let cell = cells.get_mut(block.y as usize - 1)
+                .and_then(|row| row.get_mut(block.x as usize));
+            if let Some(cell) = cell {
+                *cell = true;
+            }
+        }
+        cells
+    }
+
     pub fn lock(&mut self, tetrimino: Tetrimino) {
         let blocks = tetrimino.blocks();
         self.blocks.extend(blocks);
@@ -341,6 +516,29 @@ impl Grid {
         self.blocks = blocks;
     }
 
+    /// Pushes every block up `rows` rows and fills the rows opened at the
+    /// bottom, leaving a hole at `hole_column`. Blocks pushed out of the top
+    /// are gone, and make it return false.
+    pub fn insert_garbage(&mut self, rows: u32, hole_column: i32) -> bool {
+        let rows = rows as i32;
+        for block in &mut self.blocks {
+            block.y += rows;
+        }
+        for y in 1..rows + 1 {
+            for x in (0..self.width).filter(|&x| x != hole_column) {
+                self.blocks.push(Block {
+                    x,
+                    y,
+                    color: GARBAGE_COLOR,
+                });
+            }
+        }
+        let height = self.height;
+        let before = self.blocks.len();
+        self.blocks.retain(|block| block.y <= height);
+        self.blocks.len() == before
+    }
+
     pub fn get_full_rows(&self) -> Vec<i32> {
         let rows: Vec<i32> = (0..self.height+1).rev()
             .filter(|&row| {
@@ -381,6 +579,12 @@ impl Grid {
             })
     }
 
+    /// Whether the cell holds a locked block or lies outside the well.
+    pub fn is_filled(&self, x: i32, y: i32) -> bool {
+        x < 0 || x >= self.width || y < 1 ||
+            self.blocks.iter().any(|block| block.x == x && block.y == y)
+    }
+
     pub fn is_legal(&self, blocks: &Vec<Block>) -> bool {
         !blocks.iter().any(|ref block| {
             self.blocks.contains(&block) ||
@@ -391,3 +595,53 @@ impl Grid {
         })
     }
 }
+
+
+// Lets tests build a well block by block.
+#[cfg(test)]
+impl Grid {
+    pub fn fill(&mut self, x: i32, y: i32) {
+        self.blocks.push(Block { x, y, color: GARBAGE_COLOR });
+    }
+}
+
+
+// Lets tests put a piece anywhere in the well.
+#[cfg(test)]
+impl Tetrimino {
+    pub fn place(&mut self, x: i32, y: i32, rotation: usize) {
+        self.x = x;
+        self.y = y;
+        self.rotation.curr_idx = rotation;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn garbage_pushes_everything_up() {
+        let mut grid = Grid::new(20, 10);
+        grid.fill(0, 1);
+        assert!(grid.insert_garbage(2, 3));
+        assert!(grid.is_filled(0, 3));
+        assert!(!grid.is_filled(3, 1) && !grid.is_filled(3, 2));
+        assert_eq!(grid.blocks().len(), 1 + 2 * 9);
+    }
+
+    #[test]
+    fn garbage_drops_blocks_pushed_out_of_the_top() {
+        let mut grid = Grid::new(20, 10);
+        grid.fill(0, 19);
+        grid.fill(1, 20);
+        assert!(!grid.insert_garbage(1, 5));
+        assert!(grid.is_filled(0, 20));
+        assert!(grid.blocks().iter().all(|block| block.y <= 20));
+        assert_eq!(grid.blocks().len(), 1 + 9);
+        let cells = grid.cells();
+        assert_eq!(cells.len(), 20);
+        assert!(cells[19][0] && !cells[19][1]);
+    }
+}
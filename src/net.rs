@@ -0,0 +1,439 @@
+use std::io::{ self, BufRead, BufReader, Write };
+use std::mem;
+use std::net::{ Shutdown, TcpListener, TcpStream };
+use std::sync::mpsc::{ self, Receiver, TryRecvError };
+use std::thread;
+
+use engine::{ Action, Engine, Mode, Rules, States };
+use replay::{ self, RuleReader };
+use versus::Outcome;
+
+
+const HEADER: &str = "tetris-versus 1";
+
+
+// The protocol is lines of text. The host opens with `HEADER`, then
+// `seed`, `level` and the rules as in a replay, then `start`. After that
+// both sides send, for every update:
+//
+//     garbage <rows>             garbage taken in since the last update,
+//                                never more than the well is tall
+//     tick <n> <action>...       the update itself and what was pressed
+//
+// Each side plays its own engine and runs a copy of the other from these
+// lines, so the copy ends up exactly where the real one is, a little later.
+// `rematch` starts the next game once both sides have sent it, and `bye`
+// says goodbye.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Message {
+    Garbage(u32),
+    Tick(u64, Vec<Action>),
+    Rematch,
+    Bye,
+}
+
+
+impl Message {
+    fn parse(line: &str) -> io::Result<Message> {
+        let mut parts = line.split_whitespace();
+        let message = match parts.next() {
+            Some("garbage") => {
+                let rows = parts.next().ok_or_else(|| replay::invalid("missing rows"))?;
+                Message::Garbage(replay::parse_value(rows)? as u32)
+            },
+            Some("tick") => {
+                let tick = parts.next().ok_or_else(|| replay::invalid("missing tick"))?;
+                let actions = parts
+                    .map(|name| Action::from_name(name)
+                         .ok_or_else(|| replay::invalid("bad input action")))
+                    .collect::<io::Result<Vec<Action>>>()?;
+                Message::Tick(replay::parse_value(tick)?, actions)
+            },
+            Some("rematch") => Message::Rematch,
+            Some("bye") => Message::Bye,
+            _ => return Err(replay::invalid(&format!("bad message {}", line))),
+        };
+        Ok(message)
+    }
+
+    fn line(&self) -> String {
+        match *self {
+            Message::Garbage(rows) => format!("garbage {}", rows),
+            Message::Tick(tick, ref actions) => {
+                let mut line = format!("tick {}", tick);
+                for action in actions {
+                    line.push(' ');
+                    line.push_str(action.name());
+                }
+                line
+            },
+            Message::Rematch => "rematch".to_string(),
+            Message::Bye => "bye".to_string(),
+        }
+    }
+}
+
+
+/// The connection to the other player. Lines are read on their own thread
+/// so a slow opponent never holds up the game.
+struct Peer {
+    stream: TcpStream,
+    messages: Receiver<io::Result<Message>>,
+}
+
+
+impl Peer {
+    fn new(stream: TcpStream, reader: BufReader<TcpStream>) -> io::Result<Peer> {
+        stream.set_nodelay(true)?;
+        let (sender, messages) = mpsc::channel();
+        thread::spawn(move || {
+            for line in reader.lines() {
+                let message = line.and_then(|line| Message::parse(&line));
+                let failed = message.is_err();
+                if sender.send(message).is_err() || failed {
+                    return;
+                }
+            }
+            let closed = io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed");
+            let _ = sender.send(Err(closed));
+        });
+        Ok(Peer {
+            stream,
+            messages,
+        })
+    }
+
+    fn send(&mut self, message: &Message) -> io::Result<()> {
+        writeln!(self.stream, "{}", message.line())
+    }
+
+    /// The next message that has arrived, if any.
+    fn receive(&self) -> Option<io::Result<Message>> {
+        match self.messages.try_recv() {
+            Ok(message) => Some(message),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => {
+                Some(Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed")))
+            },
+        }
+    }
+
+    fn close(&mut self) {
+        let _ = self.send(&Message::Bye);
+        let _ = self.stream.shutdown(Shutdown::Write);
+    }
+}
+
+
+/// Versus against a player on another machine. Player 0 is the local
+/// player and player 1 the copy of the opponent.
+pub struct Online {
+    peer: Peer,
+    level: u8,
+    seed: u64,
+    rules: Rules,
+    engines: Vec<Engine>,
+    // Updates each side has played this game, and the one it topped out on.
+    ticks: [u64; 2],
+    lost: [Option<u64>; 2],
+    // Garbage the local player took in since their last update.
+    received: Vec<u32>,
+    rematch: [bool; 2],
+    closed: Option<String>,
+}
+
+
+impl Online {
+    /// Waits for a player to connect on `port` and starts a match with
+    /// them on the host's seed, level and rules.
+    pub fn host(port: u16, start_level: u8, seed: u64, rules: Rules) -> io::Result<Online> {
+        let listener = TcpListener::bind(("0.0.0.0", port))?;
+        Online::accept(&listener, start_level, seed, rules)
+    }
+
+    fn accept(listener: &TcpListener, start_level: u8, seed: u64, rules: Rules)
+              -> io::Result<Online> {
+        let (mut stream, _) = listener.accept()?;
+        let rules = Rules {
+            mode: Mode::Versus,
+            ..rules
+        };
+        writeln!(stream, "{}", HEADER)?;
+        writeln!(stream, "seed {}", seed)?;
+        writeln!(stream, "level {}", start_level)?;
+        replay::write_rules(&mut stream, &rules)?;
+        writeln!(stream, "start")?;
+        let reader = BufReader::new(stream.try_clone()?);
+        Ok(Online::new(Peer::new(stream, reader)?, start_level, seed, rules))
+    }
+
+    /// Joins the match hosted at `addr`, such as `192.168.1.20:4000`.
+    pub fn connect(addr: &str) -> io::Result<Online> {
+        let stream = TcpStream::connect(addr)?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        if line.trim() != HEADER {
+            return Err(replay::invalid("not a tetris host"));
+        }
+        let mut seed = None;
+        let mut level = None;
+        let mut rules = RuleReader::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(replay::invalid("host hung up"));
+            }
+            let mut parts = line.split_whitespace();
+            match (parts.next(), parts.next()) {
+                (Some("start"), None) => break,
+                (Some("seed"), Some(value)) => seed = Some(replay::parse_value(value)?),
+                (Some("level"), Some(value)) => level = Some(replay::parse_value(value)?),
+                (Some(key), Some(value)) => rules.read(key, value)?,
+                _ => return Err(replay::invalid("bad handshake line")),
+            }
+        }
+        let seed = seed.ok_or_else(|| replay::invalid("missing seed"))?;
+        let level = match level {
+            Some(level) if level <= 20 => level as u8,
+            _ => return Err(replay::invalid("bad start level")),
+        };
+        let peer = Peer::new(stream, reader)?;
+        Ok(Online::new(peer, level, seed, rules.finish()?))
+    }
+
+    fn new(peer: Peer, start_level: u8, seed: u64, rules: Rules) -> Online {
+        let mut online = Online {
+            peer,
+            level: start_level,
+            seed,
+            rules,
+            engines: vec![],
+            ticks: [0, 0],
+            lost: [None, None],
+            received: vec![],
+            rematch: [false, false],
+            closed: None,
+        };
+        online.start();
+        online
+    }
+
+    fn start(&mut self) {
+        self.engines = (0..2)
+            .map(|_| Engine::new(self.level, self.seed, self.rules.clone()))
+            .collect();
+        self.ticks = [0, 0];
+        self.lost = [None, None];
+        self.received.clear();
+        self.rematch = [false, false];
+    }
+
+    pub fn engine(&self, player: usize) -> &Engine {
+        &self.engines[player]
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Why the connection ended, once it has.
+    pub fn closed(&self) -> Option<&str> {
+        self.closed.as_deref()
+    }
+
+    /// The player who topped out first loses. Until the copy of the
+    /// opponent has caught up with the tick the local player topped out on
+    /// it is too early to say, which keeps both sides agreeing.
+    pub fn outcome(&self) -> Option<Outcome> {
+        match (self.lost[0], self.lost[1]) {
+            (Some(mine), Some(theirs)) if mine == theirs => Some(Outcome::Draw),
+            (Some(mine), Some(theirs)) if mine < theirs => Some(Outcome::Winner(1)),
+            (Some(_), Some(_)) => Some(Outcome::Winner(0)),
+            (Some(mine), None) if self.ticks[1] > mine => Some(Outcome::Winner(1)),
+            (None, Some(theirs)) if self.ticks[0] > theirs => Some(Outcome::Winner(0)),
+            _ => None,
+        }
+    }
+
+    /// Catches up with the opponent, then plays one update for the local
+    /// player and sends it. Once the match is over a `Restart` asks for a
+    /// rematch on the next seed.
+    pub fn tick(&mut self, actions: &[Action]) {
+        if let Err(e) = self.exchange(actions) {
+            self.closed = Some(format!("connection lost: {}", e));
+        }
+    }
+
+    fn exchange(&mut self, actions: &[Action]) -> io::Result<()> {
+        while self.closed.is_none() {
+            match self.peer.receive() {
+                Some(message) => self.on_message(message?)?,
+                None => break,
+            }
+        }
+        if self.closed.is_some() || self.rematch[0] {
+            return Ok(());
+        }
+        if self.outcome().is_some() && actions.contains(&Action::Restart) {
+            return self.request_rematch();
+        }
+
+        for rows in mem::take(&mut self.received) {
+            self.peer.send(&Message::Garbage(rows))?;
+        }
+        let tick = self.ticks[0];
+        self.engines[0].tick(actions);
+        self.ticks[0] += 1;
+        if self.lost[0].is_none() && *self.engines[0].state() == States::GameOver {
+            self.lost[0] = Some(tick);
+        }
+        self.engines[0].take_attack();
+        self.peer.send(&Message::Tick(tick, actions.to_vec()))
+    }
+
+    fn on_message(&mut self, message: Message) -> io::Result<()> {
+        match message {
+            // More than a well's worth would push everything out the top.
+            Message::Garbage(rows) if rows > self.engines[1].grid().height as u32 => {
+                return Err(replay::invalid(&format!("too much garbage {}", rows)));
+            },
+            Message::Garbage(rows) => self.engines[1].receive_garbage(rows),
+            Message::Tick(tick, actions) => {
+                self.engines[1].tick(&actions);
+                self.ticks[1] = tick + 1;
+                if self.lost[1].is_none() && *self.engines[1].state() == States::GameOver {
+                    self.lost[1] = Some(tick);
+                }
+                // The copy attacks exactly when the opponent did.
+                let rows = self.engines[1].take_attack();
+                if rows > 0 {
+                    self.engines[0].receive_garbage(rows);
+                    self.received.push(rows);
+                }
+            },
+            Message::Rematch => {
+                self.rematch[1] = true;
+                if self.rematch[0] {
+                    self.next_game();
+                } else {
+                    self.request_rematch()?;
+                }
+            },
+            Message::Bye => self.closed = Some("the opponent left".to_string()),
+        }
+        Ok(())
+    }
+
+    fn request_rematch(&mut self) -> io::Result<()> {
+        self.rematch[0] = true;
+        self.peer.send(&Message::Rematch)?;
+        if self.rematch[1] {
+            self.next_game();
+        }
+        Ok(())
+    }
+
+    fn next_game(&mut self) {
+        self.seed = self.seed.wrapping_add(1);
+        self.start();
+    }
+
+    /// Says goodbye to the opponent.
+    pub fn close(&mut self) {
+        if self.closed.is_none() {
+            self.peer.close();
+            self.closed = Some("left the match".to_string());
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // A match between two players on this machine.
+    fn connected() -> (Online, Online) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let rules = Rules {
+            lock_delay: 20,
+            ..Rules::default()
+        };
+        let host = thread::spawn(move || Online::accept(&listener, 3, 9, rules).unwrap());
+        let guest = Online::connect(&addr).unwrap();
+        (host.join().unwrap(), guest)
+    }
+
+    // Ticks `online` with no input until `done`, or fails after a while.
+    fn wait_for<F: Fn(&Online) -> bool>(online: &mut Online, done: F) {
+        for _ in 0..2000 {
+            if done(online) {
+                return;
+            }
+            online.tick(&[]);
+            thread::sleep(Duration::from_millis(1));
+        }
+        panic!("timed out");
+    }
+
+    #[test]
+    fn handshake_sends_the_game() {
+        let (host, guest) = connected();
+        assert_eq!(guest.seed(), 9);
+        assert_eq!(guest.level, 3);
+        assert_eq!(guest.rules, host.rules);
+        assert_eq!(guest.rules.mode, Mode::Versus);
+        assert_eq!(guest.rules.lock_delay, 20);
+    }
+
+    #[test]
+    fn copies_follow_in_lockstep() {
+        let (mut host, mut guest) = connected();
+        for tick in 0..300 {
+            let actions = match tick % 20 {
+                0 => vec![Action::Left, Action::Rotate],
+                1 => vec![Action::LeftReleased],
+                10 => vec![Action::HardDrop],
+                _ => vec![],
+            };
+            host.tick(&actions);
+        }
+        wait_for(&mut guest, |guest| guest.ticks[1] == 300);
+        let (real, copy) = (host.engine(0), guest.engine(1));
+        assert!(!real.grid().is_empty());
+        assert_eq!(copy.ticks(), real.ticks());
+        assert_eq!(copy.grid().blocks(), real.grid().blocks());
+        assert_eq!(copy.active().blocks(), real.active().blocks());
+        assert_eq!(copy.score(), real.score());
+    }
+
+    #[test]
+    fn garbage_reaches_the_copy() {
+        let (mut host, mut guest) = connected();
+        host.peer.send(&Message::Garbage(3)).unwrap();
+        wait_for(&mut guest, |guest| guest.engine(1).incoming_garbage() > 0);
+        assert_eq!(guest.engine(1).incoming_garbage(), 3);
+        assert_eq!(guest.closed(), None);
+    }
+
+    #[test]
+    fn too_much_garbage_ends_the_match() {
+        let (mut host, mut guest) = connected();
+        host.peer.send(&Message::Garbage(1000)).unwrap();
+        wait_for(&mut guest, |guest| guest.closed().is_some());
+        assert_eq!(guest.engine(1).incoming_garbage(), 0);
+        assert!(guest.closed().unwrap().contains("too much garbage"));
+    }
+
+    #[test]
+    fn leaving_disconnects_the_opponent() {
+        let (mut host, mut guest) = connected();
+        host.close();
+        assert_eq!(host.closed(), Some("left the match"));
+        wait_for(&mut guest, |guest| guest.closed().is_some());
+        assert_eq!(guest.closed(), Some("the opponent left"));
+    }
+}
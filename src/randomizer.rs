@@ -0,0 +1,319 @@
+use std::collections::VecDeque;
+
+use rand::{ IsaacRng, Rng, SeedableRng };
+
+use models::TetriminoType;
+
+
+const TYPES: [TetriminoType; 7] = [
+    TetriminoType::O,
+    TetriminoType::I,
+    TetriminoType::T,
+    TetriminoType::S,
+    TetriminoType::Z,
+    TetriminoType::J,
+    TetriminoType::L,
+];
+
+// How many tries the TGM randomizer gets at a piece missing from its history.
+const TGM_ROLLS: u8 = 6;
+
+
+/// Decides which piece comes next. Sendable so games can move between
+/// threads.
+pub trait Randomizer: Send {
+    fn next_type(&mut self) -> TetriminoType;
+}
+
+
+/// The randomizers a game can be started with.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RandomizerKind {
+    Bag7,
+    Bag14,
+    Random,
+    Nes,
+    Tgm,
+}
+
+
+impl RandomizerKind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            RandomizerKind::Bag7 => "bag7",
+            RandomizerKind::Bag14 => "bag14",
+            RandomizerKind::Random => "random",
+            RandomizerKind::Nes => "nes",
+            RandomizerKind::Tgm => "tgm",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<RandomizerKind> {
+        match name {
+            "bag7" => Some(RandomizerKind::Bag7),
+            "bag14" => Some(RandomizerKind::Bag14),
+            "random" => Some(RandomizerKind::Random),
+            "nes" => Some(RandomizerKind::Nes),
+            "tgm" => Some(RandomizerKind::Tgm),
+            _ => None,
+        }
+    }
+
+    pub fn create(&self, seed: u64) -> Box<dyn Randomizer> {
+        match *self {
+            RandomizerKind::Bag7 => Box::new(Bag::new(seed, 1)),
+            RandomizerKind::Bag14 => Box::new(Bag::new(seed, 2)),
+            RandomizerKind::Random => Box::new(Random::new(seed)),
+            RandomizerKind::Nes => Box::new(Nes::new(seed)),
+            RandomizerKind::Tgm => Box::new(Tgm::new(seed)),
+        }
+    }
+}
+
+
+/// Deals out shuffled bags holding `copies` of every piece.
+pub struct Bag {
+    rng: IsaacRng,
+    copies: usize,
+    bag: VecDeque<TetriminoType>,
+}
+
+
+impl Bag {
+    pub fn new(seed: u64, copies: usize) -> Bag {
+        Bag {
+            rng: seeded(seed),
+            copies,
+            bag: VecDeque::new(),
+        }
+    }
+}
+
+
+impl Randomizer for Bag {
+    fn next_type(&mut self) -> TetriminoType {
+        if self.bag.is_empty() {
+            let mut types: Vec<TetriminoType> = TYPES.iter()
+                .cycle()
+                .take(TYPES.len() * self.copies)
+                .cloned()
+                .collect();
+            shuffle(&mut self.rng, &mut types);
+            self.bag.extend(types);
+        }
+        self.bag.pop_front().unwrap()
+    }
+}
+
+
+/// Every piece is equally likely every time.
+pub struct Random {
+    rng: IsaacRng,
+}
+
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        Random {
+            rng: seeded(seed),
+        }
+    }
+}
+
+
+impl Randomizer for Random {
+    fn next_type(&mut self) -> TetriminoType {
+        pick(&mut self.rng)
+    }
+}
+
+
+/// Rolls an eight sided die where the eighth side, or repeating the last
+/// piece, means roll again once and take whatever comes up.
+pub struct Nes {
+    rng: IsaacRng,
+    last: Option<TetriminoType>,
+}
+
+
+impl Nes {
+    pub fn new(seed: u64) -> Nes {
+        Nes {
+            rng: seeded(seed),
+            last: None,
+        }
+    }
+}
+
+
+impl Randomizer for Nes {
+    fn next_type(&mut self) -> TetriminoType {
+        let roll = self.rng.gen_range(0, TYPES.len() as u32 + 1) as usize;
+        let next = match TYPES.get(roll) {
+            Some(&shape) if Some(shape) != self.last => shape,
+            _ => pick(&mut self.rng),
+        };
+        self.last = Some(next);
+        next
+    }
+}
+
+
+/// Remembers the last four pieces and rerolls a few times to avoid them.
+/// The first piece is never an S, Z or O.
+pub struct Tgm {
+    rng: IsaacRng,
+    history: VecDeque<TetriminoType>,
+    first: bool,
+}
+
+
+impl Tgm {
+    pub fn new(seed: u64) -> Tgm {
+        Tgm {
+            rng: seeded(seed),
+            history: vec![
+                TetriminoType::Z,
+                TetriminoType::S,
+                TetriminoType::Z,
+                TetriminoType::S,
+            ].into_iter().collect(),
+            first: true,
+        }
+    }
+}
+
+
+impl Randomizer for Tgm {
+    fn next_type(&mut self) -> TetriminoType {
+        let mut next = pick(&mut self.rng);
+        if self.first {
+            while matches!(next, TetriminoType::O | TetriminoType::S | TetriminoType::Z) {
+                next = pick(&mut self.rng);
+            }
+            self.first = false;
+        } else {
+            for _ in 1..TGM_ROLLS {
+                if !self.history.contains(&next) {
+                    break;
+                }
+                next = pick(&mut self.rng);
+            }
+        }
+        self.history.pop_front();
+        self.history.push_back(next);
+        next
+    }
+}
+
+
+pub fn seeded(seed: u64) -> IsaacRng {
+    IsaacRng::from_seed(&[seed as u32, (seed >> 32) as u32])
+}
+
+
+fn pick(rng: &mut IsaacRng) -> TetriminoType {
+    TYPES[rng.gen_range(0, TYPES.len() as u32) as usize]
+}
+
+
+// `Rng::shuffle` samples a `usize`, which draws a different amount of
+// randomness on 32 and 64 bit targets. Sampling `u32`s keeps a seed's
+// bag order the same everywhere.
+fn shuffle(rng: &mut IsaacRng, types: &mut [TetriminoType]) {
+    let mut i = types.len();
+    while i >= 2 {
+        i -= 1;
+        let j = rng.gen_range(0, i as u32 + 1) as usize;
+        types.swap(i, j);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [RandomizerKind; 5] = [
+        RandomizerKind::Bag7,
+        RandomizerKind::Bag14,
+        RandomizerKind::Random,
+        RandomizerKind::Nes,
+        RandomizerKind::Tgm,
+    ];
+
+    fn deal(randomizer: &mut dyn Randomizer, count: usize) -> Vec<TetriminoType> {
+        (0..count).map(|_| randomizer.next_type()).collect()
+    }
+
+    fn count(pieces: &[TetriminoType], shape: TetriminoType) -> usize {
+        pieces.iter().filter(|&&piece| piece == shape).count()
+    }
+
+    // How often a piece is the same as the one before it.
+    fn repeats(pieces: &[TetriminoType]) -> usize {
+        pieces.windows(2).filter(|pair| pair[0] == pair[1]).count()
+    }
+
+    #[test]
+    fn same_seed_same_sequence() {
+        for kind in &KINDS {
+            let first = deal(&mut *kind.create(7), 500);
+            assert_eq!(first, deal(&mut *kind.create(7), 500), "{}", kind.name());
+            assert!(first != deal(&mut *kind.create(8), 500), "{}", kind.name());
+        }
+    }
+
+    #[test]
+    fn bag7_deals_each_piece_once_per_bag() {
+        let pieces = deal(&mut Bag::new(3, 1), 700);
+        for bag in pieces.chunks(7) {
+            for &shape in &TYPES {
+                assert_eq!(count(bag, shape), 1);
+            }
+        }
+    }
+
+    #[test]
+    fn bag14_deals_each_piece_twice_per_bag() {
+        let pieces = deal(&mut Bag::new(3, 2), 1400);
+        for bag in pieces.chunks(14) {
+            for &shape in &TYPES {
+                assert_eq!(count(bag, shape), 2);
+            }
+        }
+    }
+
+    #[test]
+    fn nes_rerolls_repeats_once() {
+        let nes = deal(&mut Nes::new(5), 7000);
+        let random = deal(&mut Random::new(5), 7000);
+        for &shape in &TYPES {
+            assert!(count(&nes, shape) > 700);
+        }
+        // A repeat needs the reroll, one time in four, to come up the same
+        // piece: about one in 28, against one in 7 for `Random`.
+        assert!(repeats(&nes) < 7000 / 20, "{}", repeats(&nes));
+        assert!(repeats(&nes) * 2 < repeats(&random));
+        let triples = nes.windows(3)
+            .filter(|run| run[0] == run[1] && run[1] == run[2])
+            .count();
+        assert!(triples < 7000 / 200, "{}", triples);
+    }
+
+    #[test]
+    fn tgm_never_starts_with_s_z_or_o() {
+        for seed in 0..500 {
+            let first = Tgm::new(seed).next_type();
+            assert!(!matches!(first, TetriminoType::O | TetriminoType::S | TetriminoType::Z));
+        }
+    }
+
+    #[test]
+    fn tgm_history_reduces_repeats() {
+        let tgm = deal(&mut Tgm::new(5), 7000);
+        let random = deal(&mut Random::new(5), 7000);
+        assert!(repeats(&tgm) * 10 < repeats(&random),
+                "{} against {}", repeats(&tgm), repeats(&random));
+    }
+}
@@ -0,0 +1,63 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{ self, BufRead, BufReader, Write };
+use std::path::{ Path, PathBuf };
+
+
+/// Personal bests, one per game mode, kept in a small text file of
+/// `mode value` lines.
+pub struct Records {
+    path: PathBuf,
+    best: BTreeMap<String, u64>,
+}
+
+
+impl Records {
+    /// Reads the records at `path`. A missing file has no records yet.
+    pub fn load(path: &Path) -> io::Result<Records> {
+        let mut best = BTreeMap::new();
+        match File::open(path) {
+            Ok(f) => {
+                for line in BufReader::new(f).lines() {
+                    let line = line?;
+                    let mut parts = line.split_whitespace();
+                    if let (Some(mode), Some(value)) = (parts.next(), parts.next()) {
+                        if let Ok(value) = value.parse::<u64>() {
+                            best.insert(mode.to_string(), value);
+                        }
+                    }
+                }
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {},
+            Err(e) => return Err(e),
+        }
+        Ok(Records {
+            path: path.to_path_buf(),
+            best,
+        })
+    }
+
+    pub fn best(&self, mode: &str) -> Option<u64> {
+        self.best.get(mode).cloned()
+    }
+
+    /// Keeps `value` if it beats the record for `mode`, where higher is
+    /// better. Returns whether it did.
+    pub fn submit(&mut self, mode: &str, value: u64) -> bool {
+        match self.best(mode) {
+            Some(best) if best >= value => false,
+            _ => {
+                self.best.insert(mode.to_string(), value);
+                true
+            },
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let mut f = File::create(&self.path)?;
+        for (mode, value) in &self.best {
+            writeln!(f, "{} {}", mode, value)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+use std::io;
+
+use engine::{ Engine, States };
+use models::{ Block, Tetrimino };
+use settings::Settings;
+
+
+pub const WHITE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+pub const GRAY: [f32; 4] = [0.4, 0.4, 0.4, 1.0];
+pub const RED: [f32; 4] = [0.9, 0.1, 0.1, 1.0];
+pub const GOLD: [f32; 4] = [1.0, 0.85, 0.2, 1.0];
+pub const BLACKISH: [f32; 4] = [0.05, 0.05, 0.05, 1.0];
+
+
+/// Draws the game. Front ends get a fresh snapshot for every frame and
+/// never touch the engine themselves.
+pub trait Renderer {
+    fn render(&mut self, snapshot: &Snapshot) -> io::Result<()>;
+}
+
+
+/// Everything on screen at one moment.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    /// One board per player, the local player first.
+    pub boards: Vec<Board>,
+    pub overlay: Overlay,
+}
+
+
+/// One player's well, pieces and numbers. Blocks count rows from 0 at the
+/// bottom of the well, like `cells`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Board {
+    /// Colors of the locked blocks, as rows of columns with the bottom row
+    /// first. Rows being cleared blink, so are empty on some frames.
+    pub cells: Vec<Vec<Option<[f32; 4]>>>,
+    pub active: Vec<Block>,
+    /// Where the active piece would land, if the ghost is showing.
+    pub ghost: Vec<Block>,
+    /// The upcoming pieces to show, soonest first, where they would spawn.
+    pub queue: Vec<Vec<Block>>,
+    /// The held piece where it would spawn, if there is one.
+    pub hold: Vec<Block>,
+    pub can_hold: bool,
+    pub hud: Hud,
+}
+
+
+/// The numbers and messages beside a well.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Hud {
+    pub score: u32,
+    pub lines: u32,
+    pub level: u8,
+    /// Time left in modes with a time limit, otherwise time played.
+    pub time_ms: u64,
+    pub incoming_garbage: u32,
+    pub combo: u32,
+    pub back_to_back: u32,
+    pub clear_label: Option<String>,
+    pub perfect_clear: bool,
+}
+
+
+/// What is shown over the wells.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Overlay {
+    None,
+    Paused,
+    /// The game or match is over, with a title and lines about how it went.
+    Over(String, Vec<String>),
+}
+
+
+impl Board {
+    /// How `engine` looks with the ghost and queue length in `settings`.
+    pub fn new(engine: &Engine, settings: &Settings) -> Board {
+        let grid = engine.grid();
+        let full_rows = grid.get_full_rows();
+        let blinking = *engine.state() == States::Clearing && engine.clear_ticks() % 8 < 4;
+        let shown = |block: &Block| !(blinking && full_rows.contains(&block.y));
+
+        let mut cells = vec![vec![None; grid.width as usize]; grid.height as usize];
+        for block in grid.blocks().iter().filter(|&block| shown(block)) {
+            let cell = cells.get_mut(block.y as usize - 1)
+                .and_then(|row| row.get_mut(block.x as usize));
+            if let Some(cell) = cell {
+                *cell = Some(block.color);
+            }
+        }
+        let show_ghost = match *engine.state() {
+            States::Falling | States::Locking => settings.ghost,
+            _ => false,
+        };
+        let ghost = if show_ghost { lowered(engine.ghost()) } else { vec![] };
+        let time_ms = engine.time_left_ms().unwrap_or_else(|| engine.time_ms());
+        Board {
+            cells,
+            active: lowered(engine.active().blocks().into_iter().filter(shown).collect()),
+            ghost,
+            queue: engine.peeked().iter()
+                .take(settings.preview)
+                .map(|tetrimino| lowered(tetrimino.blocks()))
+                .collect(),
+            hold: lowered(engine.held().map(Tetrimino::blocks).unwrap_or_default()),
+            can_hold: engine.can_hold(),
+            hud: Hud {
+                score: engine.score(),
+                lines: engine.lines(),
+                level: engine.level(),
+                time_ms,
+                incoming_garbage: engine.incoming_garbage(),
+                combo: engine.combo(),
+                back_to_back: engine.back_to_back(),
+                clear_label: engine.clear_label(),
+                perfect_clear: engine.perfect_clear(),
+            },
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.cells.first().map_or(0, |row| row.len())
+    }
+
+    pub fn height(&self) -> usize {
+        self.cells.len()
+    }
+}
+
+
+// The engine counts rows from 1.
+fn lowered(blocks: Vec<Block>) -> Vec<Block> {
+    blocks.into_iter()
+        .map(|block| Block { y: block.y - 1, ..block })
+        .collect()
+}
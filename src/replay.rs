@@ -0,0 +1,347 @@
+use std::fs::File;
+use std::io::{ self, BufRead, BufReader, Write };
+use std::path::Path;
+
+use engine::{ Action, AutoRepeat, Engine, LockReset, Mode, Rules };
+use models::RotationSystem;
+use randomizer::RandomizerKind;
+
+
+const HEADER: &str = "tetris-replay 2";
+
+// Replays from before the rules were written out can't be played back
+// faithfully, as lock delay and soft drop have changed since.
+const OLD_HEADER: &str = "tetris-replay 1";
+
+// Settings `write_rules` always writes, so `RuleReader` insists on.
+const REQUIRED: [&str; 7] = [
+    "mode",
+    "rotation",
+    "randomizer",
+    "soft-drop-das",
+    "soft-drop-arr",
+    "lock-delay",
+    "lock-reset",
+];
+
+
+/// Everything needed to play a game back: how the engine was created and
+/// every action it received, keyed by the update tick it was applied on.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Replay {
+    pub seed: u64,
+    pub level: u8,
+    pub rules: Rules,
+    pub ticks: u64,
+    pub inputs: Vec<(u64, Action)>,
+}
+
+
+impl Replay {
+    pub fn new(seed: u64, level: u8, rules: Rules) -> Replay {
+        Replay {
+            seed,
+            level,
+            rules,
+            ticks: 0,
+            inputs: vec![],
+        }
+    }
+
+    pub fn record(&mut self, tick: u64, action: Action) {
+        self.inputs.push((tick, action));
+    }
+
+    pub fn finish(&mut self, ticks: u64) {
+        self.ticks = ticks;
+    }
+
+    /// Runs the whole replay without a window and returns the engine as it
+    /// was after the last recorded tick.
+    pub fn play(&self) -> Engine {
+        let mut engine = Engine::new(self.level, self.seed, self.rules.clone());
+        let mut playback = Playback::new(self.clone());
+        while let Some(actions) = playback.actions(engine.ticks()) {
+            engine.tick(&actions);
+        }
+        engine
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut f = File::create(path)?;
+        writeln!(f, "{}", HEADER)?;
+        writeln!(f, "seed {}", self.seed)?;
+        writeln!(f, "level {}", self.level)?;
+        write_rules(&mut f, &self.rules)?;
+        writeln!(f, "ticks {}", self.ticks)?;
+        for &(tick, action) in &self.inputs {
+            writeln!(f, "{} {}", tick, action.name())?;
+        }
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> io::Result<Replay> {
+        let f = File::open(path)?;
+        let mut lines = BufReader::new(f).lines();
+        let header = lines.next().unwrap_or(Ok(String::new()))?;
+        match header.trim() {
+            HEADER => {},
+            OLD_HEADER => return Err(invalid("replay from an older version of the game")),
+            _ => return Err(invalid("not a replay file")),
+        }
+        let mut seed = None;
+        let mut level = None;
+        let mut ticks = None;
+        let mut rules = RuleReader::new();
+        let mut inputs = vec![];
+        for line in lines {
+            let line = line?;
+            let mut parts = line.split_whitespace();
+            let (key, value) = match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) => (key, value),
+                _ => return Err(invalid("bad replay line")),
+            };
+            if let Ok(tick) = key.parse::<u64>() {
+                let action = Action::from_name(value)
+                    .ok_or_else(|| invalid("bad input action"))?;
+                inputs.push((tick, action));
+                continue;
+            }
+            match key {
+                "seed" => seed = Some(parse_value(value)?),
+                "level" => level = Some(parse_value(value)?),
+                "ticks" => ticks = Some(parse_value(value)?),
+                _ => rules.read(key, value)?,
+            }
+        }
+        let rules = rules.finish()?;
+        let level = level.ok_or_else(|| invalid("missing level"))?;
+        if level > 20 {
+            return Err(invalid("bad start level"));
+        }
+        Ok(Replay {
+            seed: seed.ok_or_else(|| invalid("missing seed"))?,
+            level: level as u8,
+            rules,
+            ticks: ticks.ok_or_else(|| invalid("missing ticks"))?,
+            inputs,
+        })
+    }
+}
+
+
+/// Writes the rules a game was started with as `key value` lines.
+pub fn write_rules<W: Write>(f: &mut W, rules: &Rules) -> io::Result<()> {
+    writeln!(f, "mode {}", rules.mode.describe())?;
+    writeln!(f, "rotation {}", rules.rotation.name())?;
+    writeln!(f, "randomizer {}", rules.randomizer.name())?;
+    if let Some(shift) = rules.shift {
+        writeln!(f, "das {}", shift.delay)?;
+        writeln!(f, "arr {}", shift.rate)?;
+    }
+    writeln!(f, "soft-drop-das {}", rules.soft_drop.delay)?;
+    writeln!(f, "soft-drop-arr {}", rules.soft_drop.rate)?;
+    writeln!(f, "lock-delay {}", rules.lock_delay)?;
+    writeln!(f, "lock-reset {}", rules.lock_reset.name())?;
+    Ok(())
+}
+
+
+/// Reads back the lines written by `write_rules`. Every setting but `das`
+/// and `arr` must be given.
+pub struct RuleReader {
+    rules: Rules,
+    das: Option<u8>,
+    arr: Option<u8>,
+    missing: Vec<&'static str>,
+}
+
+
+impl RuleReader {
+    pub fn new() -> RuleReader {
+        RuleReader {
+            rules: Rules::default(),
+            das: None,
+            arr: None,
+            missing: REQUIRED.to_vec(),
+        }
+    }
+
+    pub fn read(&mut self, key: &str, value: &str) -> io::Result<()> {
+        self.missing.retain(|&required| required != key);
+        match key {
+            "mode" => {
+                self.rules.mode = Mode::parse(value)
+                    .ok_or_else(|| invalid("bad game mode"))?;
+            },
+            "rotation" => {
+                self.rules.rotation = RotationSystem::from_name(value)
+                    .ok_or_else(|| invalid("bad rotation system"))?;
+            },
+            "randomizer" => {
+                self.rules.randomizer = RandomizerKind::from_name(value)
+                    .ok_or_else(|| invalid("bad randomizer"))?;
+            },
+            "das" => self.das = Some(parse_ticks(value)?),
+            "arr" => self.arr = Some(parse_ticks(value)?),
+            "soft-drop-das" => self.rules.soft_drop.delay = parse_ticks(value)?,
+            "soft-drop-arr" => self.rules.soft_drop.rate = parse_ticks(value)?,
+            "lock-delay" => self.rules.lock_delay = parse_ticks(value)?,
+            "lock-reset" => {
+                self.rules.lock_reset = LockReset::from_name(value)
+                    .ok_or_else(|| invalid("bad lock reset policy"))?;
+            },
+            _ => return Err(invalid(&format!("unknown setting {}", key))),
+        }
+        Ok(())
+    }
+
+    /// Held keys only repeat when both `das` and `arr` were given.
+    pub fn finish(self) -> io::Result<Rules> {
+        if let Some(key) = self.missing.first() {
+            return Err(invalid(&format!("missing setting {}", key)));
+        }
+        let shift = match (self.das, self.arr) {
+            (Some(delay), Some(rate)) => Some(AutoRepeat { delay, rate }),
+            (None, None) => None,
+            _ => return Err(invalid("das and arr must be set together")),
+        };
+        Ok(Rules {
+            shift,
+            ..self.rules
+        })
+    }
+}
+
+
+/// Hands a replay's recorded actions back out one tick at a time.
+pub struct Playback {
+    replay: Replay,
+    next: usize,
+}
+
+
+impl Playback {
+    pub fn new(replay: Replay) -> Playback {
+        Playback {
+            replay,
+            next: 0,
+        }
+    }
+
+    /// Actions recorded for `tick`, or `None` once the replay has ended.
+    pub fn actions(&mut self, tick: u64) -> Option<Vec<Action>> {
+        if tick >= self.replay.ticks {
+            return None;
+        }
+        let mut actions = vec![];
+        while let Some(&(at, action)) = self.replay.inputs.get(self.next) {
+            if at > tick {
+                break;
+            }
+            actions.push(action);
+            self.next += 1;
+        }
+        Some(actions)
+    }
+}
+
+
+pub fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+
+pub fn parse_value(value: &str) -> io::Result<u64> {
+    value.parse::<u64>().map_err(|_| invalid(&format!("bad value {}", value)))
+}
+
+
+fn parse_ticks(value: &str) -> io::Result<u8> {
+    value.parse::<u8>().map_err(|_| invalid(&format!("bad tick count {}", value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+
+    fn temp(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("tetris-{}-{}.replay", process::id(), name))
+    }
+
+    fn replay(rules: Rules) -> Replay {
+        let mut replay = Replay::new(12, 4, rules);
+        replay.record(0, Action::Left);
+        replay.record(3, Action::LeftReleased);
+        replay.record(3, Action::Rotate);
+        replay.record(40, Action::HardDrop);
+        replay.finish(90);
+        replay
+    }
+
+    // Saves `replay`, loads it back and returns it with the saved text.
+    fn round_trip(replay: &Replay, name: &str) -> (io::Result<Replay>, String) {
+        let path = temp(name);
+        replay.save(&path).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        let loaded = Replay::load(&path);
+        fs::remove_file(&path).unwrap();
+        (loaded, text)
+    }
+
+    fn load_text(text: &str, name: &str) -> io::Result<Replay> {
+        let path = temp(name);
+        fs::write(&path, text).unwrap();
+        let loaded = Replay::load(&path);
+        fs::remove_file(&path).unwrap();
+        loaded
+    }
+
+    #[test]
+    fn default_rules_round_trip() {
+        let replay = replay(Rules::default());
+        let (loaded, text) = round_trip(&replay, "default");
+        assert_eq!(loaded.unwrap(), replay);
+        assert!(text.starts_with("tetris-replay 2\n"));
+        assert!(text.contains("\ndas 10\n") && text.contains("\narr 2\n"));
+    }
+
+    #[test]
+    fn changed_rules_round_trip() {
+        let replay = replay(Rules {
+            mode: Mode::Sprint(40),
+            rotation: RotationSystem::Classic,
+            randomizer: RandomizerKind::Tgm,
+            shift: None,
+            soft_drop: AutoRepeat { delay: 3, rate: 1 },
+            lock_delay: 15,
+            lock_reset: LockReset::Step,
+        });
+        let (loaded, text) = round_trip(&replay, "changed");
+        assert_eq!(loaded.unwrap(), replay);
+        assert!(!text.lines().any(|line| line.starts_with("das ") || line.starts_with("arr ")));
+    }
+
+    #[test]
+    fn old_replays_are_rejected() {
+        let error = load_text("tetris-replay 1\nseed 1\nlevel 0\nticks 10\n", "old").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("older version"));
+        assert!(load_text("something else\n", "other").is_err());
+    }
+
+    #[test]
+    fn missing_rules_are_rejected() {
+        let (_, full) = round_trip(&replay(Rules::default()), "full");
+        let without = |key: &str| -> String {
+            full.lines().filter(|line| !line.starts_with(key)).collect::<Vec<_>>().join("\n")
+        };
+        let error = load_text(&without("lock-reset "), "missing").unwrap_err();
+        assert!(error.to_string().contains("missing setting lock-reset"));
+        assert!(load_text(&without("arr "), "das").is_err());
+    }
+}
@@ -0,0 +1,131 @@
+use std::cmp::min;
+
+
+// Extra garbage sent for each clear in a row after the first.
+const COMBO_ATTACK: [u32; 12] = [0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5];
+
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Spin {
+    None,
+    Mini,
+    Full,
+}
+
+
+/// What a locked piece did: how many rows it cleared, whether it was spun
+/// into place, and which streaks it extended.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Clear {
+    pub lines: u32,
+    pub spin: Spin,
+    /// Clears in a row before this one, if this one cleared anything.
+    pub combo: Option<u32>,
+    pub back_to_back: bool,
+    /// The clear left the well empty.
+    pub perfect: bool,
+}
+
+
+impl Clear {
+    /// Tetrises and T-spins that clear lines. Consecutive difficult clears
+    /// earn the back-to-back bonus.
+    pub fn is_difficult(&self) -> bool {
+        self.lines == 4 || (self.spin != Spin::None && self.lines > 0)
+    }
+
+    /// Points for the clear at level 0. The engine scales these by level.
+    pub fn points(&self) -> u32 {
+        let mut points = self.base_points();
+        if self.back_to_back {
+            points = points * 3 / 2;
+        }
+        if let Some(combo) = self.combo {
+            points += 50 * combo;
+        }
+        if self.perfect {
+            points += self.perfect_points();
+        }
+        points
+    }
+
+    /// Garbage rows the clear sends to an opponent.
+    pub fn attack(&self) -> u32 {
+        let mut rows = match (self.spin, self.lines) {
+            (_, 0) => return 0,
+            (Spin::Full, lines) => lines * 2,
+            (Spin::None, 4) => 4,
+            (_, lines) => lines - 1,
+        };
+        if self.back_to_back {
+            rows += 1;
+        }
+        if let Some(combo) = self.combo {
+            rows += COMBO_ATTACK[min(combo as usize, COMBO_ATTACK.len() - 1)];
+        }
+        if self.perfect {
+            rows += 10;
+        }
+        rows
+    }
+
+    fn perfect_points(&self) -> u32 {
+        match self.lines {
+            1 => 800,
+            2 => 1200,
+            3 => 1800,
+            4 if self.back_to_back => 3200,
+            4 => 2000,
+            _ => 0,
+        }
+    }
+
+    fn base_points(&self) -> u32 {
+        match (self.spin, self.lines) {
+            (Spin::None, 1) => 40,
+            (Spin::None, 2) => 100,
+            (Spin::None, 3) => 300,
+            (Spin::None, 4) => 1200,
+            (Spin::Mini, 0) => 100,
+            (Spin::Mini, 1) => 200,
+            (Spin::Mini, 2) => 400,
+            (Spin::Full, 0) => 400,
+            (Spin::Full, 1) => 800,
+            (Spin::Full, 2) => 1200,
+            (Spin::Full, 3) => 1600,
+            _ => 0,
+        }
+    }
+
+    pub fn label(&self) -> Option<String> {
+        let lines = match self.lines {
+            0 => "",
+            1 => " SINGLE",
+            2 => " DOUBLE",
+            3 => " TRIPLE",
+            _ => "",
+        };
+        let name = match self.spin {
+            Spin::Full => format!("T-SPIN{}", lines),
+            Spin::Mini => format!("MINI T-SPIN{}", lines),
+            Spin::None if self.lines == 4 => "TETRIS".to_string(),
+            Spin::None => return None,
+        };
+        if self.back_to_back {
+            Some(format!("B2B {}", name))
+        } else {
+            Some(name)
+        }
+    }
+}
+
+
+/// Totals for the end of game screen.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Stats {
+    pub max_combo: u32,
+    pub back_to_backs: u32,
+    pub max_back_to_back: u32,
+    pub perfect_clears: u32,
+    pub garbage_sent: u32,
+}
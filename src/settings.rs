@@ -0,0 +1,31 @@
+use std::path::PathBuf;
+
+
+/// Front end options. None of these change how the game plays out, so they
+/// are not stored in replays.
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub ghost: bool,
+    pub preview: usize,
+    pub record: Option<PathBuf>,
+    /// Let the bot play, as the second player in local versus.
+    pub bot: bool,
+    /// Play in the terminal instead of a window.
+    pub tui: bool,
+    /// Where personal bests are kept.
+    pub records: PathBuf,
+}
+
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            ghost: true,
+            preview: 5,
+            record: None,
+            bot: false,
+            tui: false,
+            records: PathBuf::from("records.txt"),
+        }
+    }
+}
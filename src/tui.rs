@@ -0,0 +1,400 @@
+use std::io::{ self, Write };
+use std::sync::mpsc::{ self, Receiver, TryRecvError };
+use std::thread;
+use std::time::{ Duration, Instant };
+
+use termion::{ clear, cursor, style };
+use termion::color::{ Bg, Fg, Rgb };
+use termion::event::Key;
+use termion::input::TermRead;
+use termion::raw::IntoRawMode;
+use termion::screen::AlternateScreen;
+
+use engine::{ Action, TICKS_PER_SECOND };
+use models::Block;
+use render::{ Board, Overlay, Renderer, Snapshot, BLACKISH, GOLD, GRAY, RED, WHITE };
+use super::{ Game, Session, format_time };
+
+
+// The screen is redrawn every this many updates, which keeps what is sent
+// over a slow connection down.
+const FRAME_TICKS: u64 = 3;
+
+// Width of the column of numbers left of the well, and of the queue to
+// its right.
+const HUD_WIDTH: usize = 12;
+const QUEUE_WIDTH: usize = 10;
+
+
+#[derive(Clone, Copy)]
+enum Cell {
+    Empty,
+    Ghost([f32; 4]),
+    Filled([f32; 4]),
+}
+
+
+/// Plays `game` in the terminal until it is quit with escape.
+pub fn run(game: &mut Game) -> io::Result<()> {
+    let stdout = io::stdout().into_raw_mode()?;
+    let mut out = cursor::HideCursor::from(AlternateScreen::from(stdout));
+    write!(out, "{}", clear::All)?;
+    let mut renderer = TermRenderer::new(out);
+    let keys = keys();
+    let tick = Duration::from_secs(1) / TICKS_PER_SECOND as u32;
+    let mut next = Instant::now();
+    let mut updates = 0;
+    loop {
+        loop {
+            match keys.try_recv() {
+                Ok(key) => {
+                    if !on_key(game, key?) {
+                        return Ok(());
+                    }
+                },
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            }
+        }
+        game.on_update();
+        if updates % FRAME_TICKS == 0 {
+            renderer.render(&game.snapshot())?;
+        }
+        updates += 1;
+
+        next += tick;
+        let now = Instant::now();
+        if next > now {
+            thread::sleep(next - now);
+        } else {
+            next = now;
+        }
+    }
+}
+
+
+/// Keys are read on their own thread, as reading waits for a key press.
+fn keys() -> Receiver<io::Result<Key>> {
+    let (sender, keys) = mpsc::channel();
+    thread::spawn(move || {
+        for key in io::stdin().keys() {
+            if sender.send(key).is_err() {
+                return;
+            }
+        }
+    });
+    keys
+}
+
+
+// Handles a key, returning false once the player asks to quit.
+fn on_key(game: &mut Game, key: Key) -> bool {
+    match key {
+        Key::Esc | Key::Ctrl('c') => return false,
+        _ if game.playback.is_some() => {},
+        Key::Char('g') => game.settings.ghost = !game.settings.ghost,
+        Key::Char('p') => game.press(0, Action::Pause),
+        _ => {
+            let pressed = match game.session {
+                Session::Versus(_) => versus_key(key),
+                _ => solo_key(key).map(|action| (0, action)),
+            };
+            if let Some((player, action)) = pressed {
+                game.press(player, action);
+                // Terminals do not say when a key comes up, so every press
+                // is a tap and holding a key relies on the terminal's
+                // key repeat.
+                if let Some(released) = released(action) {
+                    game.press(player, released);
+                }
+            }
+        },
+    }
+    true
+}
+
+
+fn solo_key(key: Key) -> Option<Action> {
+    let action = match key {
+        Key::Char(' ') => Action::HardDrop,
+        Key::Up | Key::Char('x') => Action::Rotate,
+        Key::Char('z') => Action::RotateCcw,
+        Key::Char('a') => Action::Rotate180,
+        Key::Down => Action::Down,
+        Key::Left => Action::Left,
+        Key::Right => Action::Right,
+        Key::Char('c') => Action::Hold,
+        _ => return None,
+    };
+    Some(action)
+}
+
+
+/// The versus key sets as in the window, except that the shift keys cannot
+/// be told apart in a terminal: tab holds for the left player and comma for
+/// the right one.
+fn versus_key(key: Key) -> Option<(usize, Action)> {
+    let pressed = match key {
+        Key::Char('a') => (0, Action::Left),
+        Key::Char('d') => (0, Action::Right),
+        Key::Char('s') => (0, Action::Down),
+        Key::Char('w') => (0, Action::Rotate),
+        Key::Char('q') => (0, Action::RotateCcw),
+        Key::Char('e') => (0, Action::Rotate180),
+        Key::Char('\t') => (0, Action::Hold),
+        Key::Char(' ') => (0, Action::HardDrop),
+        Key::Left => (1, Action::Left),
+        Key::Right => (1, Action::Right),
+        Key::Down => (1, Action::Down),
+        Key::Up => (1, Action::Rotate),
+        Key::Char('/') => (1, Action::RotateCcw),
+        Key::Char('.') => (1, Action::Rotate180),
+        Key::Char(',') => (1, Action::Hold),
+        Key::Char('\n') => (1, Action::HardDrop),
+        _ => return None,
+    };
+    Some(pressed)
+}
+
+
+fn released(action: Action) -> Option<Action> {
+    match action {
+        Action::Down => Some(Action::DownReleased),
+        Action::Left => Some(Action::LeftReleased),
+        Action::Right => Some(Action::RightReleased),
+        _ => None,
+    }
+}
+
+
+/// Draws with ANSI colors on a terminal, sending only the rows that
+/// changed since the last frame.
+pub struct TermRenderer<W: Write> {
+    out: W,
+    rows: Vec<String>,
+    overlay: Overlay,
+}
+
+
+impl<W: Write> TermRenderer<W> {
+    pub fn new(out: W) -> TermRenderer<W> {
+        TermRenderer {
+            out,
+            rows: vec![],
+            overlay: Overlay::None,
+        }
+    }
+}
+
+
+impl<W: Write> Renderer for TermRenderer<W> {
+    fn render(&mut self, snapshot: &Snapshot) -> io::Result<()> {
+        let panels: Vec<Vec<String>> = snapshot.boards.iter().map(panel).collect();
+        let rows: Vec<String> = (0..panels[0].len())
+            .map(|row| {
+                let parts: Vec<&str> = panels.iter().map(|panel| panel[row].as_str()).collect();
+                parts.join("  ")
+            })
+            .collect();
+        if snapshot.overlay != self.overlay {
+            self.rows.clear();
+            self.overlay = snapshot.overlay.clone();
+        }
+
+        let mut buf = vec![];
+        let mut changed = false;
+        for (y, row) in rows.iter().enumerate() {
+            if self.rows.get(y) != Some(row) {
+                write!(buf, "{}{}{}", cursor::Goto(1, y as u16 + 1), row, clear::UntilNewline)?;
+                changed = true;
+            }
+        }
+        let overlay = overlay(&self.overlay);
+        if changed && !overlay.is_empty() {
+            // A box in the middle of the screen, over the wells.
+            let width = overlay.iter().map(|line| line.len()).max().unwrap_or(0) + 4;
+            let screen_width = snapshot.boards.iter()
+                .map(|board| panel_width(board) + 2)
+                .sum::<usize>() - 2;
+            let x = screen_width.saturating_sub(width) / 2 + 1;
+            let y = rows.len().saturating_sub(overlay.len()) / 2 + 1;
+            for (idx, line) in overlay.iter().enumerate() {
+                write!(buf, "{}{}{}{:^width$}{}",
+                       cursor::Goto(x as u16, (y + idx) as u16),
+                       Bg(rgb(BLACKISH)), Fg(rgb(WHITE)), line, style::Reset,
+                       width = width)?;
+            }
+        }
+        self.rows = rows;
+        self.out.write_all(&buf)?;
+        self.out.flush()
+    }
+}
+
+
+fn panel_width(board: &Board) -> usize {
+    HUD_WIDTH + board.width() * 2 + 2 + QUEUE_WIDTH
+}
+
+
+/// One player's hold piece and numbers, well and queue as rows of text
+/// that all take up the same width on screen.
+fn panel(board: &Board) -> Vec<String> {
+    let mut hold = board.hold.clone();
+    if !board.can_hold {
+        for block in &mut hold {
+            block.color = GRAY;
+        }
+    }
+    let hold: Vec<String> = piece(&hold).into_iter()
+        .map(|row| row + &" ".repeat(HUD_WIDTH - 8))
+        .collect();
+    let hud = &board.hud;
+    let mut side = vec![
+        text("HOLD"),
+        hold[0].clone(),
+        hold[1].clone(),
+        text(""),
+        text("SCORE"),
+        text(&format!("{:0>6}", hud.score)),
+        text(""),
+        text("LINES"),
+        text(&format!("{:0>4}", hud.lines)),
+        text(""),
+        text("LEVEL"),
+        text(&format!("{:0>2}", hud.level)),
+        text(""),
+        text("TIME"),
+        text(&format_time(hud.time_ms)),
+        text(""),
+    ];
+    if hud.combo > 0 {
+        side.push(text(&format!("COMBO x{}", hud.combo)));
+    }
+    if hud.back_to_back > 0 {
+        side.push(text(&format!("B2B x{}", hud.back_to_back)));
+    }
+
+    let mut queue = vec![format!("  {:<8}", "NEXT")];
+    for blocks in &board.queue {
+        for row in &piece(blocks) {
+            queue.push(format!("  {}", row));
+        }
+        queue.push(" ".repeat(QUEUE_WIDTH));
+    }
+
+    let blank = " ".repeat(HUD_WIDTH);
+    let no_queue = " ".repeat(QUEUE_WIDTH);
+    well(board).iter().enumerate()
+        .map(|(row, line)| {
+            format!("{}{}{}",
+                    side.get(row).unwrap_or(&blank),
+                    line,
+                    queue.get(row).unwrap_or(&no_queue))
+        })
+        .collect()
+}
+
+
+/// The well with its walls and floor, and the name of the last clear
+/// under it.
+fn well(board: &Board) -> Vec<String> {
+    let (width, height) = (board.width(), board.height());
+    // Rows from the top, as they are drawn.
+    let mut cells: Vec<Vec<Cell>> = board.cells.iter().rev()
+        .map(|row| row.iter().map(|cell| cell.map_or(Cell::Empty, Cell::Filled)).collect())
+        .collect();
+    let mut put = |block: &Block, cell: Cell| {
+        if block.y >= 0 && (block.y as usize) < height && block.x >= 0 {
+            if let Some(at) = cells[height - 1 - block.y as usize].get_mut(block.x as usize) {
+                *at = cell;
+            }
+        }
+    };
+    for block in &board.ghost {
+        put(block, Cell::Ghost(block.color));
+    }
+    for block in &board.active {
+        put(block, Cell::Filled(block.color));
+    }
+
+    let incoming = board.hud.incoming_garbage as usize;
+    let mut rows: Vec<String> = cells.iter().enumerate()
+        .map(|(row, cells)| {
+            let wall = if height - row <= incoming {
+                format!("{} {}", Bg(rgb(RED)), style::Reset)
+            } else {
+                "|".to_string()
+            };
+            let mut line = wall;
+            if board.hud.perfect_clear && row == height / 2 {
+                line.push_str(&format!("{}{}{:^width$}", Bg(rgb(BLACKISH)), Fg(rgb(GOLD)),
+                                       "PERFECT CLEAR", width = width * 2));
+            } else {
+                for &cell in cells {
+                    line.push_str(&match cell {
+                        Cell::Empty => format!("{}  ", Bg(rgb(BLACKISH))),
+                        Cell::Ghost(color) => format!("{}{}[]", Bg(rgb(BLACKISH)), Fg(rgb(color))),
+                        Cell::Filled(color) => format!("{}  ", Bg(rgb(color))),
+                    });
+                }
+            }
+            line.push_str(&format!("{}|", style::Reset));
+            line
+        })
+        .collect();
+    rows.push(format!("+{}+", "-".repeat(width * 2)));
+    let label = board.hud.clear_label.clone().unwrap_or_default();
+    rows.push(format!("{:^width$.width$}", label, width = width * 2 + 2));
+    rows
+}
+
+
+/// A piece from the queue or the hold box as two rows of four cells, eight
+/// columns wide.
+fn piece(blocks: &[Block]) -> Vec<String> {
+    let left = blocks.iter().map(|block| block.x).min().unwrap_or(0);
+    let right = blocks.iter().map(|block| block.x).max().unwrap_or(0);
+    let top = blocks.iter().map(|block| block.y).max().unwrap_or(0);
+    let shift = (4 - (right - left + 1)) / 2;
+    (0..2)
+        .map(|row| {
+            (0..4)
+                .map(|col| {
+                    let found = blocks.iter()
+                        .find(|block| top - block.y == row && block.x - left + shift == col);
+                    match found {
+                        Some(block) => format!("{}  {}", Bg(rgb(block.color)), style::Reset),
+                        None => "  ".to_string(),
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+
+fn text(line: &str) -> String {
+    format!("{:<width$.width$}", line, width = HUD_WIDTH)
+}
+
+
+/// The lines shown in a box over the wells, if any.
+fn overlay(overlay: &Overlay) -> Vec<String> {
+    match *overlay {
+        Overlay::None => vec![],
+        Overlay::Paused => vec!["PAUSED".to_string()],
+        Overlay::Over(ref title, ref summary) => {
+            let mut lines = vec![title.clone(), "[spacebar to continue]".to_string(),
+                                 String::new()];
+            lines.extend(summary.iter().cloned());
+            lines
+        },
+    }
+}
+
+
+fn rgb(color: [f32; 4]) -> Rgb {
+    let channel = |value: f32| (value * 255.0) as u8;
+    Rgb(channel(color[0]), channel(color[1]), channel(color[2]))
+}
@@ -0,0 +1,84 @@
+use engine::{ Action, Engine, Mode, Rules, States };
+
+
+/// How a versus match ended.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Outcome {
+    Winner(usize),
+    /// Both players topped out on the same tick.
+    Draw,
+}
+
+
+/// Two engines playing the same piece sequence, trading garbage until one
+/// of them tops out.
+pub struct Versus {
+    engines: Vec<Engine>,
+    level: u8,
+    seed: u64,
+    rules: Rules,
+    outcome: Option<Outcome>,
+}
+
+
+impl Versus {
+    pub fn new(start_level: u8, seed: u64, rules: Rules) -> Versus {
+        let rules = Rules {
+            mode: Mode::Versus,
+            ..rules
+        };
+        Versus {
+            engines: Versus::engines(start_level, seed, &rules),
+            level: start_level,
+            seed,
+            rules,
+            outcome: None,
+        }
+    }
+
+    fn engines(start_level: u8, seed: u64, rules: &Rules) -> Vec<Engine> {
+        (0..2).map(|_| Engine::new(start_level, seed, rules.clone())).collect()
+    }
+
+    pub fn engine(&self, player: usize) -> &Engine {
+        &self.engines[player]
+    }
+
+    pub fn outcome(&self) -> Option<Outcome> {
+        self.outcome
+    }
+
+    /// Advances both players by one update with the actions each of them
+    /// pressed, then hands each player's attack to the other. Once the
+    /// match is over, a `Restart` from either player starts a rematch on
+    /// the next seed.
+    pub fn tick(&mut self, actions: &[Vec<Action>]) {
+        if self.outcome.is_some() {
+            if actions.iter().any(|actions| actions.contains(&Action::Restart)) {
+                self.seed = self.seed.wrapping_add(1);
+                self.engines = Versus::engines(self.level, self.seed, &self.rules);
+                self.outcome = None;
+            }
+            return;
+        }
+        for (engine, actions) in self.engines.iter_mut().zip(actions) {
+            engine.tick(actions);
+        }
+        let attacks: Vec<u32> = self.engines.iter_mut()
+            .map(|engine| engine.take_attack())
+            .collect();
+        for (player, &rows) in attacks.iter().enumerate() {
+            self.engines[1 - player].receive_garbage(rows);
+        }
+
+        let lost: Vec<bool> = self.engines.iter()
+            .map(|engine| *engine.state() == States::GameOver)
+            .collect();
+        self.outcome = match (lost[0], lost[1]) {
+            (true, true) => Some(Outcome::Draw),
+            (true, false) => Some(Outcome::Winner(1)),
+            (false, true) => Some(Outcome::Winner(0)),
+            (false, false) => None,
+        };
+    }
+}
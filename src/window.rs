@@ -0,0 +1,503 @@
+use std::cmp::min;
+use std::io;
+use std::path::Path;
+
+use graphics::{ Context, Text, Transformed, Viewport, image, clear, rectangle };
+use graphics::character::CharacterCache;
+use graphics::rectangle::{ Border, Rectangle };
+use glutin_window::GlutinWindow as Window;
+use opengl_graphics::{ GlGraphics, OpenGL, Texture };
+use opengl_graphics::glyph_cache::GlyphCache;
+use piston::event_loop::{ Events, EventLoop, EventSettings };
+use piston::input::{ Button, PressEvent, ReleaseEvent, Input };
+use piston::input::keyboard::Key;
+use piston::window::WindowSettings;
+
+use engine::{ Action, TICKS_PER_SECOND };
+use models::Block;
+use render::{ Board, Overlay, Renderer, Snapshot, BLACKISH, GOLD, GRAY, RED, WHITE };
+use super::{ Game, Session, format_time };
+
+
+const CLEARISH: [f32; 4] = [0.05, 0.05, 0.05, 0.7];
+const GHOST_ALPHA: f32 = 0.25;
+
+const CELL_SIZE: f64 = 40.0;
+
+// Where each player's half of the window starts in versus, and how far it
+// is shrunk from the solo layout.
+const VERSUS_OFFSETS: [(f64, f64); 2] = [(0.0, 200.0), (520.0, 200.0)];
+const VERSUS_SCALE: f64 = 0.5;
+
+
+/// Plays `game` in a window until it is closed.
+pub fn run(game: &mut Game) {
+    let opengl = OpenGL::V3_2;
+    let mut window: Window = WindowSettings::new(
+        "tetris",
+        [1040, 800])
+        .opengl(opengl)
+        .exit_on_esc(true)
+        .build()
+        .unwrap();
+    let mut renderer = GlRenderer::new(opengl);
+
+    let mut settings = EventSettings::new();
+    settings.set_ups(TICKS_PER_SECOND);
+    settings.set_max_fps(60);
+    let mut events = Events::new(settings);
+    while let Some(e) = events.next(&mut window) {
+        match e {
+            Input::Render(args) => {
+                renderer.viewport = Some(args.viewport());
+                renderer.render(&game.snapshot()).expect("cannot draw the game");
+            },
+            Input::Press(_) => on_press(game, &e),
+            Input::Release(_) => on_release(game, &e),
+            Input::Update(_) => game.on_update(),
+            _ => {},
+        }
+    }
+}
+
+
+fn on_press(game: &mut Game, e: &Input) {
+    if game.playback.is_some() {
+        return;
+    }
+    if let Some(Button::Keyboard(key)) = e.press_args() {
+        if key == Key::G {
+            game.settings.ghost = !game.settings.ghost;
+            return;
+        }
+        let pressed = match game.session {
+            _ if key == Key::P => (0, Action::Pause),
+            Session::Versus(_) => match versus_key(key) {
+                Some(pressed) => pressed,
+                None => return,
+            },
+            _ => {
+                let action = match key {
+                    Key::Space => Action::HardDrop,
+                    Key::Up | Key::X => Action::Rotate,
+                    Key::Z => Action::RotateCcw,
+                    Key::A => Action::Rotate180,
+                    Key::Down => Action::Down,
+                    Key::Left => Action::Left,
+                    Key::Right => Action::Right,
+                    Key::C | Key::LShift => Action::Hold,
+                    _ => return,
+                };
+                (0, action)
+            },
+        };
+        game.press(pressed.0, pressed.1);
+    }
+}
+
+
+fn on_release(game: &mut Game, e: &Input) {
+    if game.playback.is_some() {
+        return;
+    }
+    if let Some(Button::Keyboard(key)) = e.release_args() {
+        let pressed = match game.session {
+            Session::Versus(_) => versus_key(key),
+            _ => match key {
+                Key::Down => Some((0, Action::Down)),
+                Key::Left => Some((0, Action::Left)),
+                Key::Right => Some((0, Action::Right)),
+                _ => None,
+            },
+        };
+        let (player, action) = match pressed {
+            Some((player, Action::Down)) => (player, Action::DownReleased),
+            Some((player, Action::Left)) => (player, Action::LeftReleased),
+            Some((player, Action::Right)) => (player, Action::RightReleased),
+            _ => return,
+        };
+        game.actions[player].push(action);
+    }
+}
+
+
+/// The versus key sets: WASD with Q, E, left shift and space for the left
+/// player, the arrows with slash, period, right shift and return for the
+/// right one.
+fn versus_key(key: Key) -> Option<(usize, Action)> {
+    let pressed = match key {
+        Key::A => (0, Action::Left),
+        Key::D => (0, Action::Right),
+        Key::S => (0, Action::Down),
+        Key::W => (0, Action::Rotate),
+        Key::Q => (0, Action::RotateCcw),
+        Key::E => (0, Action::Rotate180),
+        Key::LShift => (0, Action::Hold),
+        Key::Space => (0, Action::HardDrop),
+        Key::Left => (1, Action::Left),
+        Key::Right => (1, Action::Right),
+        Key::Down => (1, Action::Down),
+        Key::Up => (1, Action::Rotate),
+        Key::Slash => (1, Action::RotateCcw),
+        Key::Period => (1, Action::Rotate180),
+        Key::RShift => (1, Action::Hold),
+        Key::Return => (1, Action::HardDrop),
+        _ => return None,
+    };
+    Some(pressed)
+}
+
+
+/// Draws with OpenGL into the window, in the part of it given by the last
+/// render event.
+pub struct GlRenderer {
+    gl: GlGraphics,
+    assets: Assets,
+    viewport: Option<Viewport>,
+}
+
+
+/// What the window draws with, loaded once it has an OpenGL context.
+struct Assets {
+    img: Texture,
+    cache: GlyphCache<'static>,
+}
+
+
+impl GlRenderer {
+    pub fn new(opengl: OpenGL) -> GlRenderer {
+        GlRenderer {
+            gl: GlGraphics::new(opengl),
+            assets: Assets {
+                img: Texture::from_path("assets/shade.png").unwrap(),
+                cache: GlyphCache::new(Path::new("assets/Verdana.ttf")).unwrap(),
+            },
+            viewport: None,
+        }
+    }
+}
+
+
+impl Renderer for GlRenderer {
+    fn render(&mut self, snapshot: &Snapshot) -> io::Result<()> {
+        let viewport = match self.viewport {
+            Some(viewport) => viewport,
+            None => return Ok(()),
+        };
+        let assets = &mut self.assets;
+        self.gl.draw(viewport, |c, gl| {
+            clear(GRAY, gl);
+
+            for (player, board) in snapshot.boards.iter().enumerate() {
+                let c = layout(snapshot.boards.len(), player, c);
+                assets.draw_well(board, &c, gl);
+                assets.draw_preview(board, &c, gl);
+                assets.draw_hold(board, &c, gl);
+                assets.draw_clear_label(board, &c, gl);
+                assets.draw_streaks(board, &c, gl);
+                assets.draw_perfect_clear(board, &c, gl);
+                assets.draw_score(board, &c, gl);
+                assets.draw_lines(board, &c, gl);
+                assets.draw_level(board, &c, gl);
+                assets.draw_time(board, &c, gl);
+            }
+
+            match snapshot.overlay {
+                Overlay::None => {},
+                Overlay::Paused => assets.draw_paused(&c, gl),
+                Overlay::Over(ref title, ref lines) => assets.draw_game_over(title, lines, &c, gl),
+            }
+        });
+        Ok(())
+    }
+}
+
+
+/// Moves and shrinks the solo layout into the player's part of the window.
+fn layout(players: usize, player: usize, c: Context) -> Context {
+    if players == 1 {
+        return c;
+    }
+    let (x, y) = VERSUS_OFFSETS[player];
+    c.trans(x, y).scale(VERSUS_SCALE, VERSUS_SCALE)
+}
+
+
+impl Assets {
+    fn draw_well(&mut self, board: &Board, c: &Context, gl: &mut GlGraphics) {
+        let locked = board.cells.iter().enumerate()
+            .flat_map(|(y, row)| row.iter().enumerate().filter_map(move |(x, cell)| {
+                cell.map(|color| Block { x: x as i32, y: y as i32, color })
+            }));
+        let blocks = board.active.iter().cloned().chain(locked);
+        let height = board.height() as i32;
+        let shade = &self.img;
+
+        rectangle(BLACKISH, [50.0, 0.0, 400.0, 800.0], c.transform, gl);
+
+        // Garbage waiting to rise, as a bar beside the well.
+        let incoming = min(board.hud.incoming_garbage, height as u32) as f64 * CELL_SIZE;
+        if incoming > 0.0 {
+            rectangle(RED, [30.0, 800.0 - incoming, 15.0, incoming], c.transform, gl);
+        }
+
+        for block in &board.ghost {
+            let x_pos = 50.0f64 + (block.x as f64 * CELL_SIZE);
+            let y_pos = 0.0f64 + ((height - 1 - block.y) as f64 * CELL_SIZE);
+            let mut color = block.color;
+            color[3] = GHOST_ALPHA;
+
+            rectangle(color, [x_pos, y_pos, CELL_SIZE, CELL_SIZE], c.transform, gl);
+        }
+
+        for block in blocks {
+            let x_cell = block.x as f64;
+            let y_cell = (height - 1 - block.y) as f64;
+            let x_pos = 50.0f64 + (x_cell * CELL_SIZE);
+            let y_pos = 0.0f64 + (y_cell * CELL_SIZE);
+            let color = block.color;
+
+            rectangle(color, [x_pos, y_pos, CELL_SIZE, CELL_SIZE], c.transform, gl);
+            image(shade, c.transform.trans(x_pos, y_pos), gl);
+        }
+    }
+
+    // Draws a piece sitting at its spawn position scaled into a HUD box
+    // whose top left corner is at `x`, `y`.
+    fn draw_blocks(&mut self, blocks: &[Block], x: f64, y: f64, cell_size: f64,
+                   c: &Context, gl: &mut GlGraphics) {
+        let shade = &self.img;
+        let scale = cell_size / CELL_SIZE;
+
+        for block in blocks {
+            let x_cell= (block.x - 2) as f64;
+            let y_cell = 20.0 - block.y as f64;
+            let x_pos = x + (x_cell * cell_size);
+            let y_pos = y + (y_cell * cell_size);
+            let color = block.color;
+
+            rectangle(color, [x_pos, y_pos, cell_size, cell_size], c.transform, gl);
+            image(shade, c.transform.trans(x_pos, y_pos).scale(scale, scale), gl);
+        }
+    }
+
+    fn draw_piece_box(&mut self, blocks: &[Block], x: f64, y: f64,
+                      c: &Context, gl: &mut GlGraphics) {
+        let box_rect = Rectangle::new(BLACKISH).border(
+            Border {
+                color: WHITE,
+                radius: 3.0,
+            });
+        box_rect.draw([x, y, 240.0, 200.0], &c.draw_state,
+                      c.transform, gl);
+
+        self.draw_blocks(blocks, x, y + 40.0, CELL_SIZE, c, gl);
+    }
+
+    fn draw_preview(&mut self, board: &Board, c: &Context, gl: &mut GlGraphics) {
+        let count = board.queue.len();
+        let height = 200.0 + 100.0 * count.saturating_sub(1) as f64;
+        let preview_rect = Rectangle::new(BLACKISH).border(
+            Border {
+                color: WHITE,
+                radius: 3.0,
+            });
+        preview_rect.draw([780.0, 50.0, 240.0, height], &c.draw_state,
+                          c.transform, gl);
+
+        for (idx, blocks) in board.queue.iter().enumerate() {
+            if idx == 0 {
+                self.draw_blocks(blocks, 780.0, 90.0, CELL_SIZE, c, gl);
+            } else {
+                let y = 260.0 + 100.0 * (idx - 1) as f64;
+                self.draw_blocks(blocks, 840.0, y, CELL_SIZE / 2.0, c, gl);
+            }
+        }
+        self.draw_label("NEXT", 850.0, 35.0, c, gl);
+    }
+
+    fn draw_hold(&mut self, board: &Board, c: &Context, gl: &mut GlGraphics) {
+        let mut held_blocks = board.hold.clone();
+        if !board.can_hold {
+            for block in &mut held_blocks {
+                block.color = GRAY;
+            }
+        }
+        self.draw_piece_box(&held_blocks, 500.0, 550.0, c, gl);
+        self.draw_label("HOLD", 570.0, 535.0, c, gl);
+    }
+
+
+    fn draw_label(&mut self, label: &str, x: f64, y: f64,
+                  c: &Context, gl: &mut GlGraphics) {
+        let font = &mut self.cache;
+        let label_rect = Rectangle::new(BLACKISH).border(
+            Border {
+                color: WHITE,
+                radius: 1.0,
+            });
+        label_rect.draw([x, y, 100.0, 30.0], &c.draw_state,
+                        c.transform, gl);
+
+        let label_text = Text::new_color(WHITE, 20);
+        let label_width = font.width(20, &label);
+        let label_x_off = (100.0 - label_width) / 2.0;
+        let label_y_off = 25.0;
+        let label_trans = c.transform.trans(x + label_x_off,
+                                            y + label_y_off);
+        label_text.draw(&label, font, &c.draw_state, label_trans, gl);
+    }
+
+
+    fn draw_textbox(&mut self, label: &str, val: &str, x: f64, y: f64,
+                    c: &Context, gl: &mut GlGraphics) {
+
+        let ref mut font = self.cache;
+        let val_rect = Rectangle::new(BLACKISH).border(
+            Border {
+                color: WHITE,
+                radius: 3.0,
+            });
+        val_rect.draw([x, y, 200.0, 80.0], &c.draw_state,
+                      c.transform, gl);
+
+        let val_text = Text::new_color(WHITE, 40);
+        let val_width = font.width(40, &val);
+        let val_x_off = (200.0 - val_width) / 2.0;
+        let val_y_off = 60.0;
+        let text_trans = c.transform.trans(x + val_x_off, y + val_y_off);
+        val_text.draw(&val, font, &c.draw_state, text_trans, gl);
+
+        self.draw_label(label, x + 50.0, y - 15.0, c, gl);
+    }
+
+
+    /// Centers a line of text under the HUD textboxes.
+    fn draw_hud_text(&mut self, line: &str, y: f64,
+                     c: &Context, gl: &mut GlGraphics) {
+        let font = &mut self.cache;
+        let text = Text::new_color(WHITE, 20);
+        let width = font.width(20, line);
+        let trans = c.transform.trans(620.0 - (width / 2.0), y);
+        text.draw(line, font, &c.draw_state, trans, gl);
+    }
+
+
+    fn draw_clear_label(&mut self, board: &Board, c: &Context, gl: &mut GlGraphics) {
+        if let Some(ref label) = board.hud.clear_label {
+            self.draw_hud_text(label, 475.0, c, gl);
+        }
+    }
+
+
+    /// Banner across the middle of the well.
+    fn draw_perfect_clear(&mut self, board: &Board, c: &Context, gl: &mut GlGraphics) {
+        if !board.hud.perfect_clear {
+            return;
+        }
+        let font = &mut self.cache;
+        let banner = "PERFECT CLEAR";
+        let text = Text::new_color(GOLD, 40);
+        let width = font.width(40, banner);
+        let trans = c.transform.trans(250.0 - (width / 2.0), 400.0);
+        text.draw(banner, font, &c.draw_state, trans, gl);
+    }
+
+
+    fn draw_streaks(&mut self, board: &Board, c: &Context, gl: &mut GlGraphics) {
+        let combo = board.hud.combo;
+        let back_to_back = board.hud.back_to_back;
+        if combo > 0 {
+            self.draw_hud_text(&format!("COMBO x{}", combo), 500.0, c, gl);
+        }
+        if back_to_back > 0 {
+            self.draw_hud_text(&format!("B2B x{}", back_to_back), 525.0, c, gl);
+        }
+    }
+
+
+    fn draw_score(&mut self, board: &Board, c: &Context, gl: &mut GlGraphics) {
+        let score = format!("{:0>6}", board.hud.score);
+        self.draw_textbox("SCORE", &score, 520.0, 40.0, c, gl);
+    }
+
+
+    fn draw_lines(&mut self, board: &Board, c: &Context, gl: &mut GlGraphics) {
+        let lines = format!("{:0>4}", board.hud.lines);
+        self.draw_textbox("LINES", &lines, 520.0, 150.0, c, gl);
+    }
+
+    fn draw_level(&mut self, board: &Board, c: &Context, gl: &mut GlGraphics) {
+        let level = format!("{:0>2}", board.hud.level);
+        self.draw_textbox("LEVEL", &level, 520.0, 260.0, c, gl);
+    }
+
+    fn draw_time(&mut self, board: &Board, c: &Context, gl: &mut GlGraphics) {
+        let time = format_time(board.hud.time_ms);
+        self.draw_textbox("TIME", &time, 520.0, 370.0, c, gl);
+    }
+
+    fn draw_game_over(&mut self, title: &str, lines: &[String],
+                      c: &Context, gl: &mut GlGraphics) {
+        let ref mut font = self.cache;
+        let mut screen_size: [f64; 4] = [0.0, 0.0, 0.0, 0.0];
+        let rect = c.viewport.unwrap().rect;
+        for (idx, _) in rect.iter().enumerate() {
+            screen_size[idx] = rect[idx] as f64;
+        }
+        let center_x = (screen_size[2]) / 2.0;
+        let center_y = (screen_size[3]) / 2.0;
+        let overlay = Rectangle::new(CLEARISH);
+        overlay.draw(screen_size, &c.draw_state, c.transform, gl);
+
+        let game_over = title;
+        let game_over_text = Text::new_color([1.0, 1.0, 1.0, 0.8], 100);
+        let game_over_width = font.width(100, &game_over);
+        let game_over_x_pos = center_x - (game_over_width / 2.0);
+        let game_over_y_pos = center_y;
+        let game_over_trans = c.transform
+            .trans(game_over_x_pos, game_over_y_pos);
+        game_over_text.draw(&game_over, font, &c.draw_state, game_over_trans, gl);
+
+        let cont = "[spacebar to continue]";
+        let cont_text = Text::new_color([1.0, 1.0, 1.0, 0.8], 20);
+        let cont_width = font.width(20, &cont);
+        let cont_x_pos = center_x - (cont_width / 2.0);
+        let cont_y_pos = game_over_y_pos + 50.0;
+        let cont_trans = c.transform
+            .trans(cont_x_pos, cont_y_pos);
+        cont_text.draw(&cont, font, &c.draw_state, cont_trans, gl);
+
+        let mut line_y_pos = cont_y_pos + 40.0;
+        for line in lines {
+            let line_width = font.width(20, line);
+            let line_trans = c.transform
+                .trans(center_x - (line_width / 2.0), line_y_pos);
+            cont_text.draw(line, font, &c.draw_state, line_trans, gl);
+            line_y_pos += 30.0;
+        }
+    }
+
+
+    fn draw_paused(&mut self, c: &Context, gl: &mut GlGraphics) {
+        let ref mut font = self.cache;
+        let mut screen_size: [f64; 4] = [0.0, 0.0, 0.0, 0.0];
+        let rect = c.viewport.unwrap().rect;
+        for (idx, _) in rect.iter().enumerate() {
+            screen_size[idx] = rect[idx] as f64;
+        }
+        let center_x = (screen_size[2]) / 2.0;
+        let center_y = (screen_size[3]) / 2.0;
+        let overlay = Rectangle::new(CLEARISH);
+        overlay.draw(screen_size, &c.draw_state, c.transform, gl);
+        let letters = "PAUSED";
+        let text = Text::new_color([1.0, 1.0, 1.0, 0.8], 100);
+        let text_width = font.width(100, &letters);
+        let x_pos = center_x - (text_width / 2.0);
+        let y_pos = center_y + (100.0 / 2.0);
+        let text_trans = c.transform
+            .trans(x_pos, y_pos)
+            .rot_deg(-22.0);
+        text.draw(&letters, font, &c.draw_state, text_trans, gl);
+    }
+}